@@ -10,7 +10,8 @@ import "./ChatRoom.css";
 const ChatRoom: React.FC<{
   roomId: string;
   wsUrl: string;
-}> = ({ roomId, wsUrl }) => {
+  token: string;
+}> = ({ roomId, wsUrl, token }) => {
   const [name, setName] = useState(generateRandomName());
   const [messages, setMessages] = useState<Message[]>([]);
   const [socket, setSocket] = useState<WebSocket | null>(null);
@@ -22,7 +23,7 @@ const ChatRoom: React.FC<{
     });
   };
   useEffect(() => {
-    const newSocket = createWebSocket(wsUrl, onMessage, setIsSocketReady);
+    const newSocket = createWebSocket(wsUrl, token, onMessage, setIsSocketReady);
     setSocket(newSocket);
     return () => {
       newSocket.close();
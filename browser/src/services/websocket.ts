@@ -1,23 +1,36 @@
-import type { Message } from "../types/types";
+import type { Message, ServerEvent } from "../types/types";
+
+// Connection records expire after CONNECTION_TTL_SECONDS (15 minutes by
+// default) without activity, and the server only refreshes them once half of
+// that has passed, so an idle tab sends a heartbeat more often than that
+const HEARTBEAT = "Heartbeat:";
+const HEARTBEAT_INTERVAL_MS = 5 * 60 * 1000;
 
 export const createWebSocket = (
   wsUrl: string,
+  token: string,
   onMessage: (message: Message) => void,
   setIsReady: (isReady: boolean) => void
 ): WebSocket => {
   console.log("Attempting connection to WebSocket");
-  const socket = new WebSocket(wsUrl);
+  // The server only accepts handshakes that offer the chat subprotocol
+  const socket = new WebSocket(`${wsUrl}?token=${encodeURIComponent(token)}`, "chat");
+  let heartbeat: ReturnType<typeof setInterval> | undefined;
   socket.onopen = function (event) {
     console.log("Connected to WebSocket");
     setIsReady(true);
+    heartbeat = setInterval(() => socket.send(HEARTBEAT), HEARTBEAT_INTERVAL_MS);
   };
   socket.onmessage = function (event) {
-    const newMessage: Message = JSON.parse(event.data);
-    console.log("Received message:", newMessage);
-    onMessage(newMessage);
+    const serverEvent: ServerEvent = JSON.parse(event.data);
+    console.log("Received event:", serverEvent);
+    if (serverEvent.type === "message") {
+      onMessage(serverEvent);
+    }
   };
   socket.onclose = function (event) {
     console.log("Disconnected from WebSocket");
+    clearInterval(heartbeat);
     setIsReady(false);
   };
   return socket;
@@ -2,3 +2,19 @@ export interface Message {
   text: string;
   author_name: string;
 }
+
+export interface ErrorFrame {
+  error: string;
+  detail: string;
+  retry_after_ms?: number;
+}
+
+// Every frame from the server carries a version and a type tag, followed by
+// the fields of that type. Only "message" is shown for now.
+export type ServerEvent = { version: number } & (
+  | ({ type: "message"; sent_at: string } & Message)
+  | ({ type: "error" } & ErrorFrame)
+  | { type: "presence"; room_id: string; name: string; status: "joined" | "left" }
+  | { type: "ack"; command: string }
+  | { type: "room"; room_id: string; member_count: number }
+);
@@ -0,0 +1,242 @@
+//! `#[derive(DynamoRecord)]` maps a struct with named fields to and from a
+//! DynamoDB item. The generated code refers to the traits in the server's
+//! `database` module, so it can only be used inside the server crate.
+//!
+//! Field attributes:
+//! - `#[dynamo(rename = "name")]` stores the field under another attribute name
+//! - `#[dynamo(default)]` uses `Default::default()` when the attribute is missing
+//! - `#[dynamo(default = "path::to::function")]` calls a function instead
+//!
+//! `Option` fields are left out of the item when `None`, and nested structs
+//! that also derive `DynamoRecord` are stored as maps.
+//!
+//! `#[derive(DynamoValue)]` stores an enum of unit variants as a string, the
+//! variant name unless it has `#[dynamo(rename = "name")]`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, LitStr, Path, Token};
+
+#[proc_macro_derive(DynamoRecord, attributes(dynamo))]
+pub fn derive_dynamo_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(DynamoValue, attributes(dynamo))]
+pub fn derive_dynamo_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_value(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+enum DefaultValue {
+    Trait,
+    Function(Path),
+}
+
+struct FieldOptions {
+    rename: Option<String>,
+    default: Option<DefaultValue>,
+}
+
+fn parse_options(attrs: &[Attribute]) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions {
+        rename: None,
+        default: None,
+    };
+    for attribute in attrs.iter().filter(|a| a.path().is_ident("dynamo")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let name: LitStr = meta.value()?.parse()?;
+                options.rename = Some(name.value());
+                Ok(())
+            } else if meta.path.is_ident("default") {
+                options.default = Some(if meta.input.peek(Token![=]) {
+                    let function: LitStr = meta.value()?.parse()?;
+                    DefaultValue::Function(function.parse()?)
+                } else {
+                    DefaultValue::Trait
+                });
+                Ok(())
+            } else {
+                Err(meta.error("expected `rename` or `default`"))
+            }
+        })?;
+    }
+    Ok(options)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            input,
+            "DynamoRecord can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            input,
+            "DynamoRecord needs named fields",
+        ));
+    };
+
+    let mut writes = vec![];
+    let mut reads = vec![];
+    for field in &fields.named {
+        let ident = field.ident.as_ref().expect("named field");
+        let options = parse_options(&field.attrs)?;
+        let key = options.rename.unwrap_or_else(|| ident.to_string());
+        writes.push(quote! {
+            if let ::std::option::Option::Some(value) =
+                crate::database::dynamo_record::ToAttributeValue::to_attribute_value(&self.#ident)
+            {
+                item.insert(#key.to_string(), value);
+            }
+        });
+        let parse = quote! {
+            crate::database::attribute_value_parser::parse_attribute(item, #key)?
+        };
+        let read = match options.default {
+            None => parse,
+            Some(default) => {
+                let default = match default {
+                    DefaultValue::Trait => quote! { ::std::default::Default::default() },
+                    DefaultValue::Function(path) => quote! { #path() },
+                };
+                quote! {
+                    match item.get(#key) {
+                        ::std::option::Option::None => #default,
+                        ::std::option::Option::Some(_) => #parse,
+                    }
+                }
+            }
+        };
+        reads.push(quote! { #ident: #read });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let item_type = quote! {
+        ::std::collections::HashMap<::std::string::String, ::aws_sdk_dynamodb::types::AttributeValue>
+    };
+    let error_type = quote! { crate::domain::errors::LogicError };
+    Ok(quote! {
+        impl #impl_generics crate::database::dynamo_record::DynamoRecord
+            for #name #type_generics #where_clause
+        {
+            fn to_item(&self) -> #item_type {
+                let mut item = ::std::collections::HashMap::new();
+                #(#writes)*
+                item
+            }
+
+            fn from_item(item: &#item_type) -> ::std::result::Result<Self, #error_type> {
+                ::std::result::Result::Ok(#name { #(#reads),* })
+            }
+        }
+
+        impl #impl_generics crate::database::dynamo_record::ToAttributeValue
+            for #name #type_generics #where_clause
+        {
+            fn to_attribute_value(
+                &self,
+            ) -> ::std::option::Option<::aws_sdk_dynamodb::types::AttributeValue> {
+                let item = crate::database::dynamo_record::DynamoRecord::to_item(self);
+                ::std::option::Option::Some(::aws_sdk_dynamodb::types::AttributeValue::M(item))
+            }
+        }
+
+        impl #impl_generics crate::database::attribute_value_parser::AttributeValueParser
+            for #name #type_generics #where_clause
+        {
+            fn parse(
+                value: ::std::option::Option<&::aws_sdk_dynamodb::types::AttributeValue>,
+            ) -> ::std::result::Result<Self, #error_type> {
+                match value {
+                    ::std::option::Option::Some(::aws_sdk_dynamodb::types::AttributeValue::M(item)) => {
+                        crate::database::dynamo_record::DynamoRecord::from_item(item)
+                    }
+                    ::std::option::Option::Some(value) => ::std::result::Result::Err(
+                        crate::database::attribute_value_parser::unexpected_type("M", value),
+                    ),
+                    ::std::option::Option::None => ::std::result::Result::Err(
+                        crate::database::attribute_value_parser::missing_attribute(),
+                    ),
+                }
+            }
+        }
+    })
+}
+
+fn expand_value(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            input,
+            "DynamoValue can only be derived for enums",
+        ));
+    };
+
+    let mut writes = vec![];
+    let mut reads = vec![];
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "DynamoValue needs unit variants",
+            ));
+        }
+        let options = parse_options(&variant.attrs)?;
+        if options.default.is_some() {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "`default` is not supported on variants",
+            ));
+        }
+        let ident = &variant.ident;
+        let value = options.rename.unwrap_or_else(|| ident.to_string());
+        writes.push(quote! { Self::#ident => #value });
+        reads.push(quote! { #value => ::std::result::Result::Ok(Self::#ident) });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let error_type = quote! { crate::domain::errors::LogicError };
+    Ok(quote! {
+        impl #impl_generics crate::database::dynamo_record::ToAttributeValue
+            for #name #type_generics #where_clause
+        {
+            fn to_attribute_value(
+                &self,
+            ) -> ::std::option::Option<::aws_sdk_dynamodb::types::AttributeValue> {
+                let value = match self {
+                    #(#writes),*
+                };
+                ::std::option::Option::Some(::aws_sdk_dynamodb::types::AttributeValue::S(
+                    value.to_string(),
+                ))
+            }
+        }
+
+        impl #impl_generics crate::database::attribute_value_parser::AttributeValueParser
+            for #name #type_generics #where_clause
+        {
+            fn parse(
+                value: ::std::option::Option<&::aws_sdk_dynamodb::types::AttributeValue>,
+            ) -> ::std::result::Result<Self, #error_type> {
+                let value: ::std::string::String =
+                    crate::database::attribute_value_parser::parse_attribute_value(value)?;
+                match value.as_str() {
+                    #(#reads,)*
+                    _ => ::std::result::Result::Err(crate::domain::errors::LogicError::DatabaseError(
+                        format!("unknown variant '{}'", value),
+                    )),
+                }
+            }
+        }
+    })
+}
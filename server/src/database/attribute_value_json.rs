@@ -0,0 +1,131 @@
+#![allow(dead_code)]
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+// Converts attribute values to and from the DynamoDB JSON format,
+// e.g. {"S": "text"} or {"L": [{"N": "1"}]}
+
+pub fn item_to_json(item: &HashMap<String, AttributeValue>) -> Value {
+    let map: Map<String, Value> = item
+        .iter()
+        .map(|(key, value)| (key.clone(), to_json(value)))
+        .collect();
+    Value::Object(map)
+}
+
+pub fn item_from_json(value: &Value) -> Result<HashMap<String, AttributeValue>, LogicError> {
+    let map = value
+        .as_object()
+        .ok_or(LogicError::DatabaseError("Expected an item".to_string()))?;
+    map.iter()
+        .map(|(key, value)| Ok((key.clone(), from_json(value)?)))
+        .collect()
+}
+
+pub fn to_json(value: &AttributeValue) -> Value {
+    match value {
+        AttributeValue::S(s) => json!({ "S": s }),
+        AttributeValue::N(n) => json!({ "N": n }),
+        AttributeValue::Bool(b) => json!({ "BOOL": b }),
+        AttributeValue::Null(n) => json!({ "NULL": n }),
+        AttributeValue::B(b) => json!({ "B": STANDARD.encode(b.as_ref()) }),
+        AttributeValue::Ss(ss) => json!({ "SS": ss }),
+        AttributeValue::Ns(ns) => json!({ "NS": ns }),
+        AttributeValue::Bs(bs) => {
+            let encoded: Vec<String> = bs.iter().map(|b| STANDARD.encode(b.as_ref())).collect();
+            json!({ "BS": encoded })
+        }
+        AttributeValue::L(l) => {
+            let values: Vec<Value> = l.iter().map(to_json).collect();
+            json!({ "L": values })
+        }
+        AttributeValue::M(m) => json!({ "M": item_to_json(m) }),
+        _ => json!({ "NULL": true }),
+    }
+}
+
+pub fn from_json(value: &Value) -> Result<AttributeValue, LogicError> {
+    let invalid = || LogicError::DatabaseError(format!("Invalid attribute value: {}", value));
+    let map = value.as_object().ok_or_else(invalid)?;
+    let (kind, inner) = map.iter().next().ok_or_else(invalid)?;
+    let as_string = || inner.as_str().map(|s| s.to_string()).ok_or_else(invalid);
+    let as_strings = || -> Result<Vec<String>, LogicError> {
+        inner
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|v| v.as_str().map(|s| s.to_string()).ok_or_else(invalid))
+            .collect()
+    };
+    let decode = |encoded: &str| {
+        STANDARD
+            .decode(encoded)
+            .map(Blob::new)
+            .map_err(|e| LogicError::DatabaseError(e.to_string()))
+    };
+    let result = match kind.as_str() {
+        "S" => AttributeValue::S(as_string()?),
+        "N" => AttributeValue::N(as_string()?),
+        "BOOL" => AttributeValue::Bool(inner.as_bool().ok_or_else(invalid)?),
+        "NULL" => AttributeValue::Null(inner.as_bool().ok_or_else(invalid)?),
+        "B" => AttributeValue::B(decode(&as_string()?)?),
+        "SS" => AttributeValue::Ss(as_strings()?),
+        "NS" => AttributeValue::Ns(as_strings()?),
+        "BS" => AttributeValue::Bs(
+            as_strings()?
+                .iter()
+                .map(|s| decode(s))
+                .collect::<Result<_, _>>()?,
+        ),
+        "L" => AttributeValue::L(
+            inner
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(from_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        "M" => AttributeValue::M(item_from_json(inner)?),
+        _ => return Err(invalid()),
+    };
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_every_type() -> Result<(), LogicError> {
+        let item = HashMap::from([
+            ("s".to_string(), AttributeValue::S("text".to_string())),
+            ("n".to_string(), AttributeValue::N("1.5".to_string())),
+            ("bool".to_string(), AttributeValue::Bool(true)),
+            ("null".to_string(), AttributeValue::Null(true)),
+            ("b".to_string(), AttributeValue::B(Blob::new(vec![0, 1, 2]))),
+            ("ss".to_string(), AttributeValue::Ss(vec!["a".to_string()])),
+            ("ns".to_string(), AttributeValue::Ns(vec!["1".to_string()])),
+            (
+                "bs".to_string(),
+                AttributeValue::Bs(vec![Blob::new(vec![3])]),
+            ),
+            (
+                "l".to_string(),
+                AttributeValue::L(vec![AttributeValue::S("a".to_string())]),
+            ),
+            (
+                "m".to_string(),
+                AttributeValue::M(HashMap::from([(
+                    "k".to_string(),
+                    AttributeValue::N("2".to_string()),
+                )])),
+            ),
+        ]);
+        let json = item_to_json(&item);
+        assert_eq!(item_from_json(&json)?, item);
+        Ok(())
+    }
+}
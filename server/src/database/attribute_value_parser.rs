@@ -1,77 +1,309 @@
 use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::primitives::Blob;
 use aws_sdk_dynamodb::types::AttributeValue;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use uuid::Uuid;
 
-pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+// Written before timestamps were RFC 3339, without a timezone. Still parsed
+// until migrate_records has rewritten every stored record.
+pub const LEGACY_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
 
+// RFC 3339 in UTC, always with microseconds, so that stored timestamps sort
+// correctly as strings
+pub fn format_datetime(datetime: &DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+// Errors say why the value could not be parsed; parse_attribute adds which
+// attribute it was
 pub trait AttributeValueParser: Sized {
     fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError>;
 }
 
+// Called by the code DynamoValue derives, which not every binary uses
+#[allow(dead_code)]
 pub fn parse_attribute_value<T: AttributeValueParser>(
     value: Option<&AttributeValue>,
 ) -> Result<T, LogicError> {
     T::parse(value)
 }
 
+pub fn parse_attribute<T: AttributeValueParser>(
+    item: &HashMap<String, AttributeValue>,
+    name: &str,
+) -> Result<T, LogicError> {
+    T::parse(item.get(name)).map_err(|e| match e {
+        LogicError::DatabaseError(reason) => {
+            LogicError::DatabaseError(format!("Attribute '{}': {}", name, reason))
+        }
+        e => e,
+    })
+}
+
+pub fn missing_attribute() -> LogicError {
+    LogicError::DatabaseError("missing".to_string())
+}
+
+pub fn unexpected_type(expected: &str, value: &AttributeValue) -> LogicError {
+    LogicError::DatabaseError(format!(
+        "expected {} but found {}",
+        expected,
+        type_name(value)
+    ))
+}
+
+pub fn type_name(value: &AttributeValue) -> &'static str {
+    match value {
+        AttributeValue::S(_) => "S",
+        AttributeValue::N(_) => "N",
+        AttributeValue::B(_) => "B",
+        AttributeValue::Bool(_) => "BOOL",
+        AttributeValue::Null(_) => "NULL",
+        AttributeValue::Ss(_) => "SS",
+        AttributeValue::Ns(_) => "NS",
+        AttributeValue::Bs(_) => "BS",
+        AttributeValue::L(_) => "L",
+        AttributeValue::M(_) => "M",
+        _ => "an unknown type",
+    }
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, LogicError> {
+    value.parse::<T>().map_err(|_| {
+        LogicError::DatabaseError(format!(
+            "could not parse '{}' as {}",
+            value,
+            std::any::type_name::<T>()
+        ))
+    })
+}
+
 impl AttributeValueParser for String {
     fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
-        let value = value.ok_or(LogicError::DatabaseError("Key not found".to_string()))?;
-        let result = value
-            .as_s()
-            .map_err(|_| LogicError::DatabaseError("Expected string".to_string()))?
-            .clone();
-        Ok(result)
+        match value.ok_or_else(missing_attribute)? {
+            AttributeValue::S(s) => Ok(s.clone()),
+            value => Err(unexpected_type("S", value)),
+        }
     }
 }
 
-impl AttributeValueParser for Option<String> {
+// A missing or NULL attribute is None
+impl<T: AttributeValueParser> AttributeValueParser for Option<T> {
     fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
         match value {
-            None => Ok(None),
-            Some(attr_value) => {
-                let result = attr_value
-                    .as_s()
-                    .map_err(|_| LogicError::DatabaseError("Expected string".to_string()))?
-                    .clone();
-                Ok(Some(result))
+            None | Some(AttributeValue::Null(_)) => Ok(None),
+            Some(value) => Ok(Some(T::parse(Some(value))?)),
+        }
+    }
+}
+
+macro_rules! impl_number_parser {
+    ($($number:ty),*) => {
+        $(
+            impl AttributeValueParser for $number {
+                fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
+                    match value.ok_or_else(missing_attribute)? {
+                        AttributeValue::N(n) => parse_number(n),
+                        value => Err(unexpected_type("N", value)),
+                    }
+                }
             }
+        )*
+    };
+}
+
+impl_number_parser!(i32, i64, u64, f64);
+
+impl AttributeValueParser for bool {
+    fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
+        match value.ok_or_else(missing_attribute)? {
+            AttributeValue::Bool(b) => Ok(*b),
+            value => Err(unexpected_type("BOOL", value)),
         }
     }
 }
 
-impl AttributeValueParser for i32 {
+impl AttributeValueParser for DateTime<Utc> {
     fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
-        let value = value.ok_or(LogicError::DatabaseError("Key not found".to_string()))?;
-        let result = value
-            .as_n()
-            .map_err(|_| LogicError::DatabaseError("Expected number".to_string()))?
-            .parse::<i32>()
-            .map_err(|_| LogicError::DatabaseError("Could not parse number".to_string()))?;
-        Ok(result)
+        let text = String::parse(value)?;
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(&text) {
+            return Ok(datetime.with_timezone(&Utc));
+        }
+        let naive_datetime =
+            NaiveDateTime::parse_from_str(&text, LEGACY_DATETIME_FORMAT).map_err(|_| {
+                LogicError::DatabaseError(format!("could not parse '{}' as a datetime", text))
+            })?;
+        Ok(naive_datetime.and_utc())
     }
 }
 
-impl AttributeValueParser for bool {
+impl AttributeValueParser for Uuid {
     fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
-        let value = value.ok_or(LogicError::DatabaseError("Key not found".to_string()))?;
-        let result = value
-            .as_bool()
-            .map_err(|_| LogicError::DatabaseError("Expected bool".to_string()))?
-            .clone();
-        Ok(result)
+        let text = String::parse(value)?;
+        Uuid::parse_str(&text).map_err(|e| {
+            LogicError::DatabaseError(format!("could not parse '{}' as a uuid: {}", text, e))
+        })
     }
 }
 
-impl AttributeValueParser for DateTime<Utc> {
+impl AttributeValueParser for Blob {
     fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
-        let value = value.ok_or(LogicError::DatabaseError("Key not found".to_string()))?;
-        let result = value
-            .as_s()
-            .map_err(|_| LogicError::DatabaseError("Expected string".to_string()))?;
-        let naive_datetime = NaiveDateTime::parse_from_str(result, DATETIME_FORMAT)
-            .map_err(|_| LogicError::DatabaseError("Could not parse datetime".to_string()))?;
-        let datetime = naive_datetime.and_utc();
-        Ok(datetime)
+        match value.ok_or_else(missing_attribute)? {
+            AttributeValue::B(b) => Ok(b.clone()),
+            value => Err(unexpected_type("B", value)),
+        }
+    }
+}
+
+impl<T: AttributeValueParser> AttributeValueParser for Vec<T> {
+    fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
+        match value.ok_or_else(missing_attribute)? {
+            AttributeValue::L(list) => list
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    T::parse(Some(value)).map_err(|e| nested_error(&format!("[{}]", index), e))
+                })
+                .collect(),
+            value => Err(unexpected_type("L", value)),
+        }
+    }
+}
+
+impl<T: AttributeValueParser> AttributeValueParser for HashMap<String, T> {
+    fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
+        match value.ok_or_else(missing_attribute)? {
+            AttributeValue::M(map) => map
+                .iter()
+                .map(|(key, value)| {
+                    let value = T::parse(Some(value)).map_err(|e| nested_error(key, e))?;
+                    Ok((key.clone(), value))
+                })
+                .collect(),
+            value => Err(unexpected_type("M", value)),
+        }
+    }
+}
+
+// DynamoDB cannot store an empty set, so a missing set is an empty one
+impl AttributeValueParser for HashSet<String> {
+    fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
+        match value {
+            None => Ok(HashSet::new()),
+            Some(AttributeValue::Ss(set)) => Ok(set.iter().cloned().collect()),
+            Some(value) => Err(unexpected_type("SS", value)),
+        }
+    }
+}
+
+macro_rules! impl_number_set_parser {
+    ($($number:ty),*) => {
+        $(
+            impl AttributeValueParser for HashSet<$number> {
+                fn parse(value: Option<&AttributeValue>) -> Result<Self, LogicError> {
+                    match value {
+                        None => Ok(HashSet::new()),
+                        Some(AttributeValue::Ns(set)) => {
+                            set.iter().map(|n| parse_number(n)).collect()
+                        }
+                        Some(value) => Err(unexpected_type("NS", value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_number_set_parser!(i32, i64, u64);
+
+fn nested_error(location: &str, error: LogicError) -> LogicError {
+    match error {
+        LogicError::DatabaseError(reason) => {
+            LogicError::DatabaseError(format!("{}: {}", location, reason))
+        }
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item() -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S(Uuid::nil().to_string())),
+            ("count".to_string(), AttributeValue::N("12".to_string())),
+            (
+                "scores".to_string(),
+                AttributeValue::L(vec![
+                    AttributeValue::N("1".to_string()),
+                    AttributeValue::S("two".to_string()),
+                ]),
+            ),
+            (
+                "tags".to_string(),
+                AttributeValue::Ss(vec!["a".to_string(), "b".to_string()]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_parses_collections() -> Result<(), LogicError> {
+        let item = item();
+        assert_eq!(parse_attribute::<Uuid>(&item, "id")?, Uuid::nil());
+        assert_eq!(parse_attribute::<u64>(&item, "count")?, 12);
+        let tags: HashSet<String> = parse_attribute(&item, "tags")?;
+        assert_eq!(tags.len(), 2);
+        assert!(parse_attribute::<HashSet<i64>>(&item, "missing")?.is_empty());
+        let counts = AttributeValue::M(HashMap::from([
+            ("a".to_string(), AttributeValue::N("1".to_string())),
+            ("b".to_string(), AttributeValue::N("2".to_string())),
+        ]));
+        let counts: HashMap<String, i64> = parse_attribute_value(Some(&counts))?;
+        assert_eq!(counts["b"], 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parses_rfc3339_and_legacy_datetimes() -> Result<(), LogicError> {
+        let expected = DateTime::from_timestamp(1_700_000_000, 500_000).unwrap();
+        let formatted = format_datetime(&expected);
+        assert_eq!(formatted, "2023-11-14T22:13:20.000500Z");
+        for text in [
+            formatted.as_str(),
+            "2023-11-14 22:13:20.000500",
+            "2023-11-14T23:13:20.0005+01:00",
+        ] {
+            let value = AttributeValue::S(text.to_string());
+            let parsed: DateTime<Utc> = parse_attribute_value(Some(&value))?;
+            assert_eq!(parsed, expected);
+        }
+        let value = AttributeValue::S("yesterday".to_string());
+        assert!(parse_attribute_value::<DateTime<Utc>>(Some(&value)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_name_attribute_and_reason() {
+        let item = item();
+        let error = |result: Result<(), LogicError>| result.unwrap_err().to_string();
+        assert_eq!(
+            error(parse_attribute::<String>(&item, "name").map(|_| ())),
+            "[DatabaseError] Attribute 'name': missing"
+        );
+        assert_eq!(
+            error(parse_attribute::<bool>(&item, "count").map(|_| ())),
+            "[DatabaseError] Attribute 'count': expected BOOL but found N"
+        );
+        assert_eq!(
+            error(parse_attribute::<Vec<i64>>(&item, "scores").map(|_| ())),
+            "[DatabaseError] Attribute 'scores': [1]: expected N but found S"
+        );
+        assert_eq!(
+            error(parse_attribute::<i32>(&item, "id").map(|_| ())),
+            "[DatabaseError] Attribute 'id': expected N but found S"
+        );
     }
 }
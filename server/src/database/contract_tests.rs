@@ -0,0 +1,598 @@
+// Behavior every IDatabase must share with DynamoDB, run against each backend
+// so that they cannot drift apart. A new backend only needs a Backend impl and
+// a test calling run_contracts.
+use super::db_cloud::DatabaseCloud;
+use super::db_local::DatabaseLocal;
+use super::db_sqlite::DatabaseSqlite;
+use super::db_trait::IDatabase;
+use super::expression::Item;
+use super::table_schema::{KeySchema, TableSchema};
+use super::websocket_table::WebsocketTable;
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::operation::query::builders::QueryInputBuilder;
+use aws_sdk_dynamodb::operation::query::QueryInput;
+use aws_sdk_dynamodb::operation::scan::ScanInput;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, BillingMode, ConditionCheck, Delete, Get, GlobalSecondaryIndex,
+    KeySchemaElement, KeyType, Projection, ProjectionType, Put, ScalarAttributeType,
+    TransactGetItem, TransactWriteItem, Update,
+};
+use axum::async_trait;
+use futures_util::future::{BoxFuture, FutureExt};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[async_trait]
+trait Backend: Send + Sync {
+    // Creates the table, returning a database that can reach it
+    async fn create_table(&self, schema: &TableSchema) -> Result<Arc<dyn IDatabase>, LogicError>;
+}
+
+struct LocalBackend;
+
+#[async_trait]
+impl Backend for LocalBackend {
+    async fn create_table(&self, schema: &TableSchema) -> Result<Arc<dyn IDatabase>, LogicError> {
+        // A small page size, so that paging is exercised without large items
+        let db = DatabaseLocal::with_schemas(vec![schema.clone()])
+            .await
+            .with_max_page_bytes(200);
+        Ok(Arc::new(db))
+    }
+}
+
+struct SqliteBackend;
+
+#[async_trait]
+impl Backend for SqliteBackend {
+    async fn create_table(&self, schema: &TableSchema) -> Result<Arc<dyn IDatabase>, LogicError> {
+        let db = DatabaseSqlite::with_schemas(":memory:", vec![schema.clone()]).await?;
+        Ok(Arc::new(db))
+    }
+}
+
+struct EmulatorBackend {
+    db: Arc<DatabaseCloud>,
+    tables: Mutex<Vec<String>>,
+}
+
+impl EmulatorBackend {
+    async fn delete_tables(&self) -> Result<(), LogicError> {
+        let tables = std::mem::take(&mut *self.tables.lock().unwrap());
+        for table_name in tables {
+            self.db
+                .client()
+                .delete_table()
+                .table_name(table_name)
+                .send()
+                .await
+                .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Backend for EmulatorBackend {
+    async fn create_table(&self, schema: &TableSchema) -> Result<Arc<dyn IDatabase>, LogicError> {
+        create_table(&self.db, schema).await?;
+        let table_name = schema.table_name.clone();
+        self.tables.lock().unwrap().push(table_name);
+        Ok(self.db.clone())
+    }
+}
+
+// Every key attribute is a string, as they are in terraform/dynamodb.tf
+async fn create_table(db: &DatabaseCloud, schema: &TableSchema) -> Result<(), LogicError> {
+    let to_error =
+        |e: aws_sdk_dynamodb::error::BuildError| LogicError::DatabaseError(e.to_string());
+    let key_elements = |key: &KeySchema| -> Result<Vec<KeySchemaElement>, LogicError> {
+        let hash = KeySchemaElement::builder()
+            .attribute_name(&key.hash_key)
+            .key_type(KeyType::Hash)
+            .build()
+            .map_err(to_error)?;
+        let mut elements = vec![hash];
+        if let Some(range_key) = &key.range_key {
+            let range = KeySchemaElement::builder()
+                .attribute_name(range_key)
+                .key_type(KeyType::Range)
+                .build()
+                .map_err(to_error)?;
+            elements.push(range);
+        }
+        Ok(elements)
+    };
+    let attributes: BTreeSet<&String> = schema
+        .primary_key
+        .attributes()
+        .chain(schema.indexes.values().flat_map(|key| key.attributes()))
+        .collect();
+    let attributes = attributes
+        .into_iter()
+        .map(|name| {
+            AttributeDefinition::builder()
+                .attribute_name(name)
+                .attribute_type(ScalarAttributeType::S)
+                .build()
+                .map_err(to_error)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let indexes = schema
+        .indexes
+        .iter()
+        .map(|(index_name, key)| {
+            let projection = Projection::builder()
+                .projection_type(ProjectionType::All)
+                .build();
+            GlobalSecondaryIndex::builder()
+                .index_name(index_name)
+                .set_key_schema(Some(key_elements(key)?))
+                .projection(projection)
+                .build()
+                .map_err(to_error)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    db.client()
+        .create_table()
+        .table_name(&schema.table_name)
+        .set_attribute_definitions(Some(attributes))
+        .set_key_schema(Some(key_elements(&schema.primary_key)?))
+        .set_global_secondary_indexes((!indexes.is_empty()).then_some(indexes))
+        .billing_mode(BillingMode::PayPerRequest)
+        .send()
+        .await
+        .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+    Ok(())
+}
+
+type Contract = fn(Arc<dyn IDatabase>, String) -> BoxFuture<'static, Result<(), LogicError>>;
+
+const CONTRACTS: [(&str, Contract); 8] = [
+    ("put_then_get", |db, table| put_then_get(db, table).boxed()),
+    ("failed_condition_cancels_transaction", |db, table| {
+        failed_condition_cancels_transaction(db, table).boxed()
+    }),
+    ("update_creates_and_checks", |db, table| {
+        update_creates_and_checks(db, table).boxed()
+    }),
+    ("delete_removes_item", |db, table| {
+        delete_removes_item(db, table).boxed()
+    }),
+    ("query_reads_index", |db, table| {
+        query_reads_index(db, table).boxed()
+    }),
+    ("query_pages_resume", |db, table| {
+        query_pages_resume(db, table).boxed()
+    }),
+    ("scan_pages_resume", |db, table| {
+        scan_pages_resume(db, table).boxed()
+    }),
+    ("one_operation_per_item", |db, table| {
+        one_operation_per_item(db, table).boxed()
+    }),
+];
+
+const RANGE_KEY_CONTRACTS: [(&str, Contract); 2] = [
+    ("query_range_conditions", |db, table| {
+        query_range_conditions(db, table).boxed()
+    }),
+    ("query_runs_backwards", |db, table| {
+        query_runs_backwards(db, table).boxed()
+    }),
+];
+
+// DynamoDB deletes expired items up to a few days after their TTL, so only
+// the databases emulating it are expected to hide them straight away
+const EXPIRY_CONTRACTS: [(&str, Contract); 1] = [("expired_items_are_absent", |db, table| {
+    expired_items_are_absent(db, table).boxed()
+})];
+
+// Each contract gets its own copy of the table
+async fn run_table_contracts(
+    backend: &dyn Backend,
+    schema: fn() -> TableSchema,
+    contracts: &[(&str, Contract)],
+) -> Result<(), LogicError> {
+    for (name, contract) in contracts {
+        let mut schema = schema();
+        schema.table_name = format!("contract-{}", uuid::Uuid::new_v4());
+        let db = backend.create_table(&schema).await?;
+        contract(db, schema.table_name.clone())
+            .await
+            .map_err(|e| LogicError::DatabaseError(format!("{} failed: {}", name, e)))?;
+    }
+    Ok(())
+}
+
+// The Websocket table is keyed as in terraform/dynamodb.tf
+async fn run_contracts(backend: &dyn Backend) -> Result<(), LogicError> {
+    run_table_contracts(backend, WebsocketTable::schema, &CONTRACTS).await?;
+    run_table_contracts(backend, messages_schema, &RANGE_KEY_CONTRACTS).await
+}
+
+fn messages_schema() -> TableSchema {
+    TableSchema::new("messages", KeySchema::composite("room_id", "sent_at"))
+}
+
+fn item(id: &str, room_id: &str, n: i32) -> Item {
+    HashMap::from([
+        ("id".to_string(), AttributeValue::S(id.to_string())),
+        (
+            "room_id".to_string(),
+            AttributeValue::S(room_id.to_string()),
+        ),
+        ("n".to_string(), AttributeValue::N(n.to_string())),
+    ])
+}
+
+fn key(id: &str) -> Item {
+    HashMap::from([("id".to_string(), AttributeValue::S(id.to_string()))])
+}
+
+fn put(table: &str, item: Item, condition: Option<&str>) -> TransactWriteItem {
+    let put = Put::builder()
+        .table_name(table)
+        .set_item(Some(item))
+        .set_condition_expression(condition.map(str::to_string))
+        .build()
+        .unwrap();
+    TransactWriteItem::builder().put(put).build()
+}
+
+async fn get(db: &Arc<dyn IDatabase>, table: &str, id: &str) -> Result<Option<Item>, LogicError> {
+    let get = Get::builder()
+        .table_name(table)
+        .set_key(Some(key(id)))
+        .build()
+        .unwrap();
+    let response = db
+        .read_single(TransactGetItem::builder().get(get).build())
+        .await?;
+    Ok(response.item)
+}
+
+fn ids(items: &[Item]) -> Vec<String> {
+    let mut ids: Vec<String> = items
+        .iter()
+        .map(|item| item["id"].as_s().unwrap().clone())
+        .collect();
+    ids.sort();
+    ids
+}
+
+async fn put_then_get(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    db.write_single(put(&table, item("id1", "room", 1), None))
+        .await?;
+    assert_eq!(get(&db, &table, "id1").await?, Some(item("id1", "room", 1)));
+    assert_eq!(get(&db, &table, "missing").await?, None);
+    // A put replaces the whole item
+    let mut replacement = key("id1");
+    replacement.insert("other".to_string(), AttributeValue::Bool(true));
+    db.write_single(put(&table, replacement.clone(), None))
+        .await?;
+    assert_eq!(get(&db, &table, "id1").await?, Some(replacement));
+    Ok(())
+}
+
+async fn failed_condition_cancels_transaction(
+    db: Arc<dyn IDatabase>,
+    table: String,
+) -> Result<(), LogicError> {
+    db.write_single(put(&table, item("id1", "room", 1), None))
+        .await?;
+    let duplicate = db
+        .write_single(put(
+            &table,
+            item("id1", "room", 2),
+            Some("attribute_not_exists(id)"),
+        ))
+        .await;
+    assert!(matches!(
+        duplicate,
+        Err(LogicError::ConditionalCheckFailed(_))
+    ));
+    let check = ConditionCheck::builder()
+        .table_name(&table)
+        .set_key(Some(key("id1")))
+        .condition_expression("n = :n")
+        .expression_attribute_values(":n", AttributeValue::N("2".to_string()))
+        .build()
+        .unwrap();
+    let result = db
+        .write(vec![
+            put(&table, item("id2", "room", 1), None),
+            TransactWriteItem::builder().condition_check(check).build(),
+        ])
+        .await;
+    assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+    assert_eq!(get(&db, &table, "id1").await?, Some(item("id1", "room", 1)));
+    assert_eq!(get(&db, &table, "id2").await?, None);
+    Ok(())
+}
+
+async fn update_creates_and_checks(
+    db: Arc<dyn IDatabase>,
+    table: String,
+) -> Result<(), LogicError> {
+    let increment = |expected: &str| {
+        let update = Update::builder()
+            .table_name(&table)
+            .set_key(Some(key("id1")))
+            .update_expression("SET n = if_not_exists(n, :zero) + :one")
+            .condition_expression("attribute_not_exists(id) OR n = :expected")
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":expected", AttributeValue::N(expected.to_string()))
+            .build()
+            .unwrap();
+        TransactWriteItem::builder().update(update).build()
+    };
+    db.write_single(increment("0")).await?;
+    db.write_single(increment("1")).await?;
+    let stale = db.write_single(increment("1")).await;
+    assert!(matches!(stale, Err(LogicError::ConditionalCheckFailed(_))));
+    let stored = get(&db, &table, "id1").await?.unwrap();
+    assert_eq!(stored["n"], AttributeValue::N("2".to_string()));
+    Ok(())
+}
+
+async fn delete_removes_item(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    db.write_single(put(&table, item("id1", "room", 1), None))
+        .await?;
+    let delete = |condition: &str| {
+        let delete = Delete::builder()
+            .table_name(&table)
+            .set_key(Some(key("id1")))
+            .condition_expression(condition)
+            .expression_attribute_values(":n", AttributeValue::N("1".to_string()))
+            .build()
+            .unwrap();
+        TransactWriteItem::builder().delete(delete).build()
+    };
+    let result = db.write_single(delete("n <> :n")).await;
+    assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+    db.write_single(delete("n = :n")).await?;
+    assert_eq!(get(&db, &table, "id1").await?, None);
+    Ok(())
+}
+
+fn room_query(table: &str, room_id: &str) -> QueryInputBuilder {
+    QueryInput::builder()
+        .table_name(table)
+        .index_name("room_id_index")
+        .key_condition_expression("room_id = :room_id")
+        .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+}
+
+async fn query_reads_index(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    db.write(vec![
+        put(&table, item("id1", "room", 1), None),
+        put(&table, item("id2", "room", 2), None),
+        put(&table, item("id3", "other", 3), None),
+        put(&table, key("id4"), None),
+    ])
+    .await?;
+    let page = db.query(room_query(&table, "room")).await?;
+    assert_eq!(ids(&page.items), ["id1", "id2"]);
+    assert!(page.last_evaluated_key.is_none());
+    let filtered = room_query(&table, "room")
+        .filter_expression("n > :n")
+        .expression_attribute_values(":n", AttributeValue::N("1".to_string()));
+    assert_eq!(ids(&db.query(filtered).await?.items), ["id2"]);
+    Ok(())
+}
+
+async fn query_pages_resume(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    let writes = (0..5)
+        .map(|i| put(&table, item(&format!("id{}", i), "room", i), None))
+        .collect();
+    db.write(writes).await?;
+    let mut items = vec![];
+    let mut pages = 0;
+    let mut start_key = None;
+    loop {
+        let query = room_query(&table, "room")
+            .limit(2)
+            .set_exclusive_start_key(start_key);
+        let page = db.query(query).await?;
+        pages += 1;
+        assert!(page.items.len() <= 2);
+        items.extend(page.items);
+        match page.last_evaluated_key {
+            Some(key) => start_key = Some(key),
+            None => break,
+        }
+    }
+    assert!(pages >= 3);
+    assert_eq!(ids(&items), ["id0", "id1", "id2", "id3", "id4"]);
+    Ok(())
+}
+
+async fn scan_pages_resume(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    let writes = (0..5)
+        .map(|i| put(&table, item(&format!("id{}", i), "room", i), None))
+        .collect();
+    db.write(writes).await?;
+    let mut items = vec![];
+    let mut start_key = None;
+    loop {
+        let scan = ScanInput::builder()
+            .table_name(&table)
+            .limit(2)
+            .filter_expression("n <> :n")
+            .expression_attribute_values(":n", AttributeValue::N("3".to_string()))
+            .set_exclusive_start_key(start_key);
+        let page = db.scan(scan).await?;
+        items.extend(page.items);
+        match page.last_evaluated_key {
+            Some(key) => start_key = Some(key),
+            None => break,
+        }
+    }
+    assert_eq!(ids(&items), ["id0", "id1", "id2", "id4"]);
+    Ok(())
+}
+
+async fn one_operation_per_item(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    let result = db
+        .write(vec![
+            put(&table, item("id1", "room", 1), None),
+            put(&table, item("id1", "room", 2), None),
+        ])
+        .await;
+    assert!(matches!(result, Err(LogicError::ValidationError(_))));
+    assert_eq!(get(&db, &table, "id1").await?, None);
+    Ok(())
+}
+
+fn message(room_id: &str, sent_at: &str) -> Item {
+    HashMap::from([
+        (
+            "room_id".to_string(),
+            AttributeValue::S(room_id.to_string()),
+        ),
+        (
+            "sent_at".to_string(),
+            AttributeValue::S(sent_at.to_string()),
+        ),
+    ])
+}
+
+fn sent_at(items: &[Item]) -> Vec<String> {
+    items
+        .iter()
+        .map(|item| item["sent_at"].as_s().unwrap().clone())
+        .collect()
+}
+
+const SENT_AT: [&str; 6] = [
+    "2024-01-01",
+    "2024-01-02",
+    "2024-01-03",
+    "2024-01-04",
+    "2024-01-05",
+    "2024-02-01",
+];
+
+async fn put_messages(db: &Arc<dyn IDatabase>, table: &str) -> Result<(), LogicError> {
+    // Written out of order, with another room's message amongst them
+    let mut writes: Vec<TransactWriteItem> = SENT_AT
+        .iter()
+        .rev()
+        .map(|at| put(table, message("room", at), None))
+        .collect();
+    writes.push(put(table, message("other", "2024-01-03"), None));
+    db.write(writes).await
+}
+
+fn room_messages(table: &str, range_condition: &str, values: &[&str]) -> QueryInputBuilder {
+    let mut query = QueryInput::builder()
+        .table_name(table)
+        .key_condition_expression(format!("room_id = :room_id AND {}", range_condition))
+        .expression_attribute_values(":room_id", AttributeValue::S("room".to_string()));
+    for (i, value) in values.iter().enumerate() {
+        query = query
+            .expression_attribute_values(format!(":v{}", i), AttributeValue::S(value.to_string()));
+    }
+    query
+}
+
+async fn query_range_conditions(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    put_messages(&db, &table).await?;
+    let cases: [(&str, &[&str], &[&str]); 4] = [
+        ("begins_with(sent_at, :v0)", &["2024-01"], &SENT_AT[..5]),
+        (
+            "sent_at BETWEEN :v0 AND :v1",
+            &["2024-01-02", "2024-01-04"],
+            &SENT_AT[1..4],
+        ),
+        ("sent_at < :v0", &["2024-01-03"], &SENT_AT[..2]),
+        ("sent_at > :v0", &["2024-01-04"], &SENT_AT[4..]),
+    ];
+    for (condition, values, expected) in cases {
+        let page = db.query(room_messages(&table, condition, values)).await?;
+        assert_eq!(sent_at(&page.items), expected, "{}", condition);
+    }
+    Ok(())
+}
+
+async fn query_runs_backwards(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    put_messages(&db, &table).await?;
+    let mut items = vec![];
+    let mut start_key = None;
+    loop {
+        let query = room_messages(&table, "sent_at > :v0", &["2000"])
+            .scan_index_forward(false)
+            .limit(4)
+            .set_exclusive_start_key(start_key);
+        let page = db.query(query).await?;
+        items.extend(page.items);
+        match page.last_evaluated_key {
+            Some(key) => start_key = Some(key),
+            None => break,
+        }
+    }
+    let mut expected = SENT_AT.to_vec();
+    expected.reverse();
+    assert_eq!(sent_at(&items), expected);
+    Ok(())
+}
+
+async fn expired_items_are_absent(db: Arc<dyn IDatabase>, table: String) -> Result<(), LogicError> {
+    let now = chrono::Utc::now().timestamp();
+    let with_ttl = |id: &str, ttl: i64| {
+        let mut item = item(id, "room", 1);
+        item.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
+        item
+    };
+    db.write(vec![
+        put(&table, with_ttl("id1", now - 10), None),
+        put(&table, with_ttl("id2", now + 1000), None),
+        put(&table, item("id3", "room", 1), None),
+    ])
+    .await?;
+    assert_eq!(get(&db, &table, "id1").await?, None);
+    let page = db.query(room_query(&table, "room")).await?;
+    assert_eq!(ids(&page.items), ["id2", "id3"]);
+    let page = db.scan(ScanInput::builder().table_name(&table)).await?;
+    assert_eq!(ids(&page.items), ["id2", "id3"]);
+    // An expired item no longer exists as far as conditions are concerned
+    db.write_single(put(
+        &table,
+        item("id1", "room", 2),
+        Some("attribute_not_exists(id)"),
+    ))
+    .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_local_contract() -> Result<(), LogicError> {
+    run_contracts(&LocalBackend).await?;
+    run_table_contracts(&LocalBackend, WebsocketTable::schema, &EXPIRY_CONTRACTS).await
+}
+
+#[tokio::test]
+async fn test_sqlite_contract() -> Result<(), LogicError> {
+    run_contracts(&SqliteBackend).await?;
+    run_table_contracts(&SqliteBackend, WebsocketTable::schema, &EXPIRY_CONTRACTS).await
+}
+
+// Start an emulator, for example with
+// `docker run -p 8000:8000 amazon/dynamodb-local`, then run
+// `cargo test -- --ignored`. DYNAMODB_ENDPOINT defaults to localhost:8000.
+#[tokio::test]
+#[ignore = "needs a DynamoDB emulator"]
+async fn test_emulator_contract() -> Result<(), LogicError> {
+    let endpoint =
+        std::env::var("DYNAMODB_ENDPOINT").unwrap_or_else(|_| "http://localhost:8000".to_string());
+    let backend = EmulatorBackend {
+        db: Arc::new(DatabaseCloud::with_endpoint(&endpoint).await),
+        tables: Mutex::new(vec![]),
+    };
+    let result = run_contracts(&backend).await;
+    backend.delete_tables().await?;
+    result
+}
@@ -0,0 +1,375 @@
+#![allow(dead_code)]
+use super::attribute_value_json::item_to_json;
+use super::db_trait::{IDatabase, QueryPage};
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::operation::query::builders::QueryInputBuilder;
+use aws_sdk_dynamodb::operation::scan::builders::ScanInputBuilder;
+use aws_sdk_dynamodb::types::{AttributeValue, ItemResponse, TransactGetItem, TransactWriteItem};
+use axum::async_trait;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(2);
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entry<T> {
+    value: T,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct TableCache {
+    items: HashMap<String, Entry<ItemResponse>>,
+    queries: HashMap<String, Entry<QueryPage>>,
+}
+
+impl TableCache {
+    fn len(&self) -> usize {
+        self.items.len() + self.queries.len()
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        self.items.retain(|_, entry| entry.expires_at > now);
+        self.queries.retain(|_, entry| entry.expires_at > now);
+    }
+}
+
+// Caches gets and queries for a short time, so that a warm Lambda or the
+// local server does not read the same records for every message. Any write
+// to a table, even one that fails, drops everything cached for that table.
+// Other processes' writes are only seen once the TTL has passed.
+pub struct DatabaseCache {
+    inner: Arc<dyn IDatabase>,
+    ttl: Duration,
+    tables: Mutex<HashMap<String, TableCache>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DatabaseCache {
+    pub fn new(inner: Arc<dyn IDatabase>) -> Self {
+        Self::with_ttl(inner, DEFAULT_TTL)
+    }
+
+    pub fn from_env(inner: Arc<dyn IDatabase>) -> Self {
+        let ttl = env::var("DB_CACHE_TTL_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_TTL);
+        Self::with_ttl(inner, ttl)
+    }
+
+    pub fn with_ttl(inner: Arc<dyn IDatabase>, ttl: Duration) -> Self {
+        DatabaseCache {
+            inner,
+            ttl,
+            tables: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn invalidate_table(&self, table_name: &str) {
+        self.tables.lock().unwrap().remove(table_name);
+    }
+
+    fn lookup<T: Clone>(
+        &self,
+        table_name: &str,
+        key: &str,
+        entries: fn(&TableCache) -> &HashMap<String, Entry<T>>,
+    ) -> Option<T> {
+        let tables = self.tables.lock().unwrap();
+        let cached = tables
+            .get(table_name)
+            .and_then(|table| entries(table).get(key))
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.value.clone());
+        drop(tables);
+        if cached.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return cached;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // Misses are rare once the cache is warm, so they carry the running
+        // totals for the hit rate
+        let stats = self.stats();
+        tracing::info!(
+            table = table_name,
+            hits = stats.hits,
+            misses = stats.misses,
+            "database cache miss"
+        );
+        None
+    }
+
+    fn store<T>(
+        &self,
+        table_name: &str,
+        key: String,
+        value: T,
+        entries: fn(&mut TableCache) -> &mut HashMap<String, Entry<T>>,
+    ) {
+        let now = Instant::now();
+        let mut tables = self.tables.lock().unwrap();
+        let total: usize = tables.values().map(TableCache::len).sum();
+        if total >= MAX_ENTRIES {
+            tables
+                .values_mut()
+                .for_each(|table| table.remove_expired(now));
+        }
+        let table = tables.entry(table_name.to_string()).or_default();
+        let expires_at = now + self.ttl;
+        entries(table).insert(key, Entry { value, expires_at });
+    }
+}
+
+fn written_table(item: &TransactWriteItem) -> Option<&str> {
+    if let Some(put) = &item.put {
+        Some(&put.table_name)
+    } else if let Some(delete) = &item.delete {
+        Some(&delete.table_name)
+    } else if let Some(update) = &item.update {
+        Some(&update.table_name)
+    } else {
+        item.condition_check
+            .as_ref()
+            .map(|check| check.table_name.as_str())
+    }
+}
+
+// Queries are cached by everything except the values that only the filter
+// uses. Those are assumed to be volatile, like the current time in the
+// connection expiry filter, and callers must re-check them on cached items.
+fn query_key(query: &QueryInputBuilder) -> String {
+    let names: BTreeMap<_, _> = query
+        .get_expression_attribute_names()
+        .iter()
+        .flatten()
+        .collect();
+    let key_condition = query.get_key_condition_expression().as_deref();
+    let key_values: HashMap<String, AttributeValue> = query
+        .get_expression_attribute_values()
+        .iter()
+        .flatten()
+        .filter(|(name, _)| key_condition.is_some_and(|condition| references(condition, name)))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let start_key = query.get_exclusive_start_key().as_ref().map(item_to_json);
+    json!({
+        "index_name": query.get_index_name(),
+        "key_condition": key_condition,
+        "filter": query.get_filter_expression(),
+        "projection": query.get_projection_expression(),
+        "names": names,
+        "key_values": item_to_json(&key_values),
+        "start_key": start_key,
+        "limit": query.get_limit(),
+        "forward": query.get_scan_index_forward(),
+    })
+    .to_string()
+}
+
+// Whether the expression uses the placeholder, and not just one that starts
+// with the same name
+fn references(expression: &str, placeholder: &str) -> bool {
+    expression.match_indices(placeholder).any(|(start, _)| {
+        let rest = &expression[start + placeholder.len()..];
+        !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+    })
+}
+
+#[async_trait]
+impl IDatabase for DatabaseCache {
+    async fn read_single(&self, item: TransactGetItem) -> Result<ItemResponse, LogicError> {
+        let Some(get) = &item.get else {
+            return self.inner.read_single(item).await;
+        };
+        let table_name = get.table_name.clone();
+        let key = item_to_json(&get.key).to_string();
+        if let Some(response) = self.lookup(&table_name, &key, |table| &table.items) {
+            return Ok(response);
+        }
+        let response = self.inner.read_single(item).await?;
+        self.store(&table_name, key, response.clone(), |table| &mut table.items);
+        Ok(response)
+    }
+
+    async fn write(&self, items: Vec<TransactWriteItem>) -> Result<(), LogicError> {
+        let tables: Vec<String> = items
+            .iter()
+            .filter_map(written_table)
+            .map(str::to_string)
+            .collect();
+        let result = self.inner.write(items).await;
+        // Also after a failed write, since a failed condition means the
+        // cached item was stale
+        for table_name in tables {
+            self.invalidate_table(&table_name);
+        }
+        result
+    }
+
+    async fn write_single(&self, item: TransactWriteItem) -> Result<(), LogicError> {
+        self.write(vec![item]).await
+    }
+
+    async fn query(&self, query: QueryInputBuilder) -> Result<QueryPage, LogicError> {
+        let table_name = query.get_table_name().clone().unwrap_or_default();
+        let key = query_key(&query);
+        if let Some(page) = self.lookup(&table_name, &key, |table| &table.queries) {
+            return Ok(page);
+        }
+        let page = self.inner.query(query).await?;
+        self.store(&table_name, key, page.clone(), |table| &mut table.queries);
+        Ok(page)
+    }
+
+    // Scans read whole tables for maintenance, so they are not cached
+    async fn scan(&self, scan: ScanInputBuilder) -> Result<QueryPage, LogicError> {
+        self.inner.scan(scan).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::db_local::DatabaseLocal;
+    use crate::database::websocket_table::WebsocketTable;
+    use crate::domain::websocket_record::WebsocketRecord;
+    use tracing_test::traced_test;
+
+    async fn cached(ttl: Duration) -> (Arc<DatabaseCache>, Arc<dyn IDatabase>) {
+        let local: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let cache = Arc::new(DatabaseCache::with_ttl(local.clone(), ttl));
+        (cache, local)
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_counts_hits_and_misses() -> Result<(), LogicError> {
+        let (cache, _) = cached(DEFAULT_TTL).await;
+        let db: Arc<dyn IDatabase> = cache.clone();
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id1", "room"), &db).await?;
+        for _ in 0..3 {
+            WebsocketTable::from_db("id1", &db).await?;
+            WebsocketTable::get_room_connections("room", &db).await?;
+        }
+        assert_eq!(cache.stats(), CacheStats { hits: 4, misses: 2 });
+        assert!(logs_contain("hits=0 misses=2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_values_are_not_part_of_the_key() -> Result<(), LogicError> {
+        let (cache, _) = cached(DEFAULT_TTL).await;
+        let query = |now: i64| {
+            QueryInputBuilder::default()
+                .table_name("table")
+                .key_condition_expression("id = :id")
+                .filter_expression("#ttl > :now")
+                .expression_attribute_names("#ttl", "ttl")
+                .expression_attribute_values(":id", AttributeValue::S("id1".to_string()))
+                .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        };
+        cache.query(query(1)).await?;
+        cache.query(query(2)).await?;
+        let other_id = query(1).expression_attribute_values(":id", AttributeValue::S("id2".into()));
+        cache.query(other_id).await?;
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_references_whole_placeholders() {
+        assert!(references("id = :id AND a = :b", ":id"));
+        assert!(!references("id = :id_2", ":id"));
+    }
+
+    #[tokio::test]
+    async fn test_writes_invalidate_table() -> Result<(), LogicError> {
+        let (cache, _) = cached(DEFAULT_TTL).await;
+        let db: Arc<dyn IDatabase> = cache.clone();
+        let mut record = WebsocketRecord::new_with_room("id1", "room");
+        WebsocketTable::to_db(&mut record, &db).await?;
+        assert_eq!(
+            WebsocketTable::get_room_connections("room", &db)
+                .await?
+                .len(),
+            1
+        );
+        // A user update moves the connection to another room
+        record.room_id = "other".to_string();
+        WebsocketTable::to_db(&mut record, &db).await?;
+        assert!(WebsocketTable::get_room_connections("room", &db)
+            .await?
+            .is_empty());
+        assert_eq!(WebsocketTable::from_db("id1", &db).await?.room_id, "other");
+        // And disconnecting removes it
+        db.write_single(WebsocketTable::delete(&record)?).await?;
+        assert!(WebsocketTable::from_db("id1", &db).await.is_err());
+        assert_eq!(cache.stats().hits, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_invalidates_stale_entry() -> Result<(), LogicError> {
+        let (cache, local) = cached(DEFAULT_TTL).await;
+        let db: Arc<dyn IDatabase> = cache.clone();
+        WebsocketTable::to_db(&mut WebsocketRecord::new("id1"), &db).await?;
+        let mut stale = WebsocketTable::from_db("id1", &db).await?;
+        // Saved by another process, so the cache does not know
+        let mut other = stale.clone();
+        WebsocketTable::to_db(&mut other, &local).await?;
+        let result = WebsocketTable::to_db(&mut stale, &db).await;
+        assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+        assert_eq!(WebsocketTable::from_db("id1", &db).await?.version, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() -> Result<(), LogicError> {
+        let (cache, local) = cached(Duration::from_millis(20)).await;
+        let db: Arc<dyn IDatabase> = cache.clone();
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id1", "room"), &db).await?;
+        assert_eq!(
+            WebsocketTable::get_room_connections("room", &db)
+                .await?
+                .len(),
+            1
+        );
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id2", "room"), &local).await?;
+        assert_eq!(
+            WebsocketTable::get_room_connections("room", &db)
+                .await?
+                .len(),
+            1
+        );
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(
+            WebsocketTable::get_room_connections("room", &db)
+                .await?
+                .len(),
+            2
+        );
+        Ok(())
+    }
+}
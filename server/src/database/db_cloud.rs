@@ -1,13 +1,18 @@
 #![allow(dead_code)]
-use super::db_trait::IDatabase;
+use super::db_trait::{IDatabase, QueryPage};
 use crate::domain::{errors::LogicError, vec_utils};
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::{self, BehaviorVersion};
+use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
 use aws_sdk_dynamodb::operation::query::builders::QueryInputBuilder;
-use aws_sdk_dynamodb::types::{AttributeValue, ItemResponse, TransactGetItem, TransactWriteItem};
+use aws_sdk_dynamodb::operation::scan::builders::ScanInputBuilder;
+use aws_sdk_dynamodb::operation::transact_get_items::TransactGetItemsError;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{
+    CancellationReason, ItemResponse, TransactGetItem, TransactWriteItem,
+};
 use aws_sdk_dynamodb::{config::Region, Client};
 use axum::async_trait;
-use std::collections::HashMap;
 use std::env;
 
 pub struct DatabaseCloud {
@@ -26,6 +31,22 @@ impl DatabaseCloud {
         let client = Client::new(&config);
         DatabaseCloud { client }
     }
+
+    // For DynamoDB Local and other emulators, which accept any credentials
+    pub async fn with_endpoint(endpoint_url: &str) -> Self {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(endpoint_url)
+            .test_credentials()
+            .load()
+            .await;
+        let client = Client::new(&config);
+        DatabaseCloud { client }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
 }
 
 #[async_trait]
@@ -37,7 +58,12 @@ impl IDatabase for DatabaseCloud {
             .transact_items(item)
             .send()
             .await
-            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+            .map_err(|e| match e.as_service_error() {
+                Some(TransactGetItemsError::TransactionCanceledException(cancelled)) => {
+                    cancellation_error(cancelled.cancellation_reasons(), e.to_string())
+                }
+                _ => to_logic_error(e),
+            })?;
         let items = result
             .responses
             .ok_or(LogicError::DatabaseError("No response".to_string()))?;
@@ -54,27 +80,74 @@ impl IDatabase for DatabaseCloud {
             .set_transact_items(Some(items))
             .send()
             .await;
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => Err(LogicError::DatabaseError(e.to_string())),
-        }
+        result.map(|_| ()).map_err(|e| match e.as_service_error() {
+            Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) => {
+                cancellation_error(cancelled.cancellation_reasons(), e.to_string())
+            }
+            _ => to_logic_error(e),
+        })
     }
 
     async fn write_single(&self, item: TransactWriteItem) -> Result<(), LogicError> {
         self.write(vec![item]).await
     }
 
-    async fn query(
-        &self,
-        query: QueryInputBuilder,
-    ) -> Result<Vec<HashMap<String, AttributeValue>>, LogicError> {
+    async fn query(&self, query: QueryInputBuilder) -> Result<QueryPage, LogicError> {
         let result = query
             .send_with(&self.client)
             .await
-            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
-        let items = result
-            .items
-            .ok_or(LogicError::DatabaseError("No response".to_string()))?;
-        Ok(items)
+            .map_err(to_logic_error)?;
+        Ok(QueryPage {
+            items: result.items.unwrap_or_default(),
+            last_evaluated_key: result.last_evaluated_key,
+        })
+    }
+
+    async fn scan(&self, scan: ScanInputBuilder) -> Result<QueryPage, LogicError> {
+        let result = scan.send_with(&self.client).await.map_err(to_logic_error)?;
+        Ok(QueryPage {
+            items: result.items.unwrap_or_default(),
+            last_evaluated_key: result.last_evaluated_key,
+        })
+    }
+}
+
+// Maps the error codes that callers handle differently to their own
+// LogicError, and everything else to DatabaseError
+fn to_logic_error<E, R>(error: SdkError<E, R>) -> LogicError
+where
+    E: ProvideErrorMetadata,
+{
+    let code = error.as_service_error().and_then(|e| e.code());
+    let message = match error.as_service_error().and_then(|e| e.message()) {
+        Some(message) => message.to_string(),
+        None => error.to_string(),
+    };
+    match code {
+        Some("ThrottlingException")
+        | Some("ProvisionedThroughputExceededException")
+        | Some("RequestLimitExceeded") => LogicError::Throttled(message),
+        Some("TransactionConflictException") => LogicError::TransactionConflict(message),
+        Some("ConditionalCheckFailedException") => LogicError::ConditionalCheckFailed(message),
+        Some("ValidationException") => LogicError::ValidationError(message),
+        Some("ResourceNotFoundException") => LogicError::ResourceNotFound(message),
+        _ => LogicError::DatabaseError(message),
+    }
+}
+
+// A cancelled transaction gives a reason for each item, most of them "None".
+// A failed condition takes precedence, since retrying cannot fix it.
+fn cancellation_error(reasons: &[CancellationReason], message: String) -> LogicError {
+    let has = |code: &str| reasons.iter().any(|reason| reason.code() == Some(code));
+    if has("ConditionalCheckFailed") {
+        LogicError::ConditionalCheckFailed(message)
+    } else if has("ValidationError") {
+        LogicError::ValidationError(message)
+    } else if has("TransactionConflict") {
+        LogicError::TransactionConflict(message)
+    } else if has("ThrottlingError") || has("ProvisionedThroughputExceeded") {
+        LogicError::Throttled(message)
+    } else {
+        LogicError::DatabaseError(message)
     }
 }
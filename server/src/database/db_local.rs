@@ -1,51 +1,212 @@
 #![allow(dead_code)]
-use super::attribute_value_parser::parse_attribute_value;
-use super::db_trait::IDatabase;
+use super::db_trait::{IDatabase, QueryPage};
+use super::expression::{apply_update, evaluate_condition, ExpressionContext, Item};
+use super::local_journal::{JournalEntry, LocalJournal, Tables};
+use super::local_query::{now, scan_page, PreparedQuery, MAX_PAGE_BYTES};
+use super::rate_limit_table::RateLimitTable;
+use super::table_schema::{KeySchema, TableSchema};
+use super::websocket_table::WebsocketTable;
 use crate::domain::{errors::LogicError, vec_utils};
 use aws_sdk_dynamodb::operation::query::builders::QueryInputBuilder;
+use aws_sdk_dynamodb::operation::scan::builders::ScanInputBuilder;
 use aws_sdk_dynamodb::operation::transact_get_items::builders::TransactGetItemsOutputBuilder;
-use aws_sdk_dynamodb::types::{
-    AttributeValue, Delete, ItemResponse, Put, TransactGetItem, TransactWriteItem,
-};
+use aws_sdk_dynamodb::types::{ItemResponse, TransactGetItem, TransactWriteItem};
 use axum::async_trait;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
 
-pub struct FakeItem {
-    pub hash_map: HashMap<String, AttributeValue>,
-}
+const SNAPSHOT_EVERY: usize = 1000;
 
+// Items are stored per table name, then by primary key. Without a journal,
+// everything is lost when the process exits.
 pub struct DatabaseLocal {
-    table: RwLock<HashMap<String, FakeItem>>,
-    primary_key_column: String,
+    tables: RwLock<Tables>,
+    schemas: HashMap<String, TableSchema>,
+    // Used for tables without a registered schema
+    fallback_schema: TableSchema,
+    journal: Option<Mutex<LocalJournal>>,
+    max_page_bytes: usize,
 }
 
 impl DatabaseLocal {
     pub async fn new() -> Self {
-        let table = RwLock::new(HashMap::new());
-        let primary_key_column = "id".to_string();
+        Self::with_schemas(default_schemas()).await
+    }
+
+    pub async fn with_schemas(schemas: Vec<TableSchema>) -> Self {
+        Self::build(Tables::new(), schemas, None)
+    }
+
+    // Restores the tables from the snapshot and journal in `dir`, then
+    // journals every subsequent write there
+    pub async fn with_persistence(dir: &Path) -> Result<Self, LogicError> {
+        let (journal, tables) = LocalJournal::open(dir, SNAPSHOT_EVERY)?;
+        Ok(Self::build(tables, default_schemas(), Some(journal)))
+    }
+
+    fn build(tables: Tables, schemas: Vec<TableSchema>, journal: Option<LocalJournal>) -> Self {
+        let schemas = schemas
+            .into_iter()
+            .map(|schema| (schema.table_name.clone(), schema))
+            .collect();
         DatabaseLocal {
-            table,
-            primary_key_column,
+            tables: RwLock::new(tables),
+            schemas,
+            fallback_schema: TableSchema::new("", KeySchema::hash("id")),
+            journal: journal.map(Mutex::new),
+            max_page_bytes: MAX_PAGE_BYTES,
+        }
+    }
+
+    // Lets tests reach the page limit without writing megabytes of items
+    pub fn with_max_page_bytes(mut self, max_page_bytes: usize) -> Self {
+        self.max_page_bytes = max_page_bytes;
+        self
+    }
+
+    fn schema(&self, table_name: &str) -> &TableSchema {
+        self.schemas
+            .get(table_name)
+            .unwrap_or(&self.fallback_schema)
+    }
+
+    // Expired items stay stored until they are overwritten, but are treated
+    // as already deleted by every read, query and condition
+    fn get_live<'a>(&self, tables: &'a Tables, table_name: &str, key: &str) -> Option<&'a Item> {
+        let item = tables.get(table_name)?.get(key)?;
+        let expired = self.schema(table_name).is_expired(item, now());
+        (!expired).then_some(item)
+    }
+
+    fn primary_key(&self, table_name: &str, item: &Item) -> Result<String, LogicError> {
+        self.schema(table_name).primary_key.storage_key(item)
+    }
+
+    // Checks the item's condition against the current tables and works out
+    // the change it would make, without applying it
+    fn prepare(
+        &self,
+        tables: &Tables,
+        item: TransactWriteItem,
+    ) -> Result<PendingWrite, LogicError> {
+        let existing = |table_name: &str, key: &str| self.get_live(tables, table_name, key);
+        if let Some(put) = item.put {
+            let key = self.primary_key(&put.table_name, &put.item)?;
+            let context = ExpressionContext::new(
+                put.expression_attribute_names.as_ref(),
+                put.expression_attribute_values.as_ref(),
+            );
+            let current = existing(&put.table_name, &key);
+            check_condition(put.condition_expression.as_deref(), &context, current, &key)?;
+            Ok(PendingWrite::new(
+                put.table_name,
+                key,
+                Change::Put(put.item),
+            ))
+        } else if let Some(delete) = item.delete {
+            let key = self.primary_key(&delete.table_name, &delete.key)?;
+            let context = ExpressionContext::new(
+                delete.expression_attribute_names.as_ref(),
+                delete.expression_attribute_values.as_ref(),
+            );
+            let current = existing(&delete.table_name, &key);
+            check_condition(
+                delete.condition_expression.as_deref(),
+                &context,
+                current,
+                &key,
+            )?;
+            Ok(PendingWrite::new(delete.table_name, key, Change::Delete))
+        } else if let Some(update) = item.update {
+            let key = self.primary_key(&update.table_name, &update.key)?;
+            let context = ExpressionContext::new(
+                update.expression_attribute_names.as_ref(),
+                update.expression_attribute_values.as_ref(),
+            );
+            let current = existing(&update.table_name, &key);
+            check_condition(
+                update.condition_expression.as_deref(),
+                &context,
+                current,
+                &key,
+            )?;
+            // Like DynamoDB, updating a missing item creates it from its key
+            let mut updated = current.cloned().unwrap_or(update.key);
+            apply_update(&update.update_expression, &context, &mut updated)?;
+            Ok(PendingWrite::new(
+                update.table_name,
+                key,
+                Change::Put(updated),
+            ))
+        } else if let Some(check) = item.condition_check {
+            let key = self.primary_key(&check.table_name, &check.key)?;
+            let context = ExpressionContext::new(
+                check.expression_attribute_names.as_ref(),
+                check.expression_attribute_values.as_ref(),
+            );
+            let current = existing(&check.table_name, &key);
+            check_condition(Some(&check.condition_expression), &context, current, &key)?;
+            Ok(PendingWrite::new(check.table_name, key, Change::None))
+        } else {
+            Err(LogicError::DatabaseError(
+                "Expected a Put, Delete, Update or ConditionCheck".to_string(),
+            ))
         }
     }
 
-    fn write_put(&self, put: Put) -> Result<(), LogicError> {
-        let primary_key = parse_attribute_value::<String>(put.item.get(&self.primary_key_column))?;
-        let item = FakeItem {
-            hash_map: put.item.clone(),
+    // Called with the tables write lock held, so entries are journaled in
+    // the order they were applied
+    fn journal(&self, tables: &Tables, entries: &[JournalEntry]) -> Result<(), LogicError> {
+        let Some(journal) = &self.journal else {
+            return Ok(());
         };
-        let mut hash_map = self.table.write().unwrap();
-        hash_map.insert(primary_key.to_string(), item);
+        let mut journal = journal.lock().unwrap();
+        if journal.append(entries)? {
+            journal.write_snapshot(tables)?;
+        }
         Ok(())
     }
+}
+
+enum Change {
+    Put(Item),
+    Delete,
+    None,
+}
+
+struct PendingWrite {
+    table_name: String,
+    key: String,
+    change: Change,
+}
+
+impl PendingWrite {
+    fn new(table_name: String, key: String, change: Change) -> Self {
+        PendingWrite {
+            table_name,
+            key,
+            change,
+        }
+    }
+}
 
-    fn write_delete(&self, delete: Delete) -> Result<(), LogicError> {
-        let primary_key =
-            parse_attribute_value::<String>(delete.key.get(&self.primary_key_column))?;
-        let mut hash_map = self.table.write().unwrap();
-        hash_map.remove(&primary_key.to_string());
+fn check_condition(
+    expression: Option<&str>,
+    context: &ExpressionContext,
+    item: Option<&Item>,
+    key: &str,
+) -> Result<(), LogicError> {
+    let Some(expression) = expression else {
+        return Ok(());
+    };
+    if evaluate_condition(expression, context, item)? {
         Ok(())
+    } else {
+        Err(LogicError::ConditionalCheckFailed(format!(
+            "Transaction cancelled, the conditional request failed for {}",
+            key
+        )))
     }
 }
 
@@ -55,15 +216,14 @@ impl IDatabase for DatabaseLocal {
         let get = item.get.ok_or(LogicError::DatabaseError(
             "Only Gets are supported".to_string(),
         ))?;
-        let hash_map = self.table.read().unwrap();
-        let primary_key = parse_attribute_value::<String>(get.key.get(&self.primary_key_column))?;
-        let item = hash_map
-            .get(&primary_key)
-            .ok_or(LogicError::DatabaseError("Item not found".to_string()))?;
-
-        let item_response = ItemResponse::builder()
-            .set_item(Some(item.hash_map.clone()))
-            .build();
+        let tables = self.tables.read().unwrap();
+        let primary_key = self.primary_key(&get.table_name, &get.key)?;
+        // Like DynamoDB, a missing item is a response without an item
+        let item = self
+            .get_live(&tables, &get.table_name, &primary_key)
+            .cloned();
+
+        let item_response = ItemResponse::builder().set_item(item).build();
         let output = TransactGetItemsOutputBuilder::default()
             .responses(item_response)
             .build();
@@ -75,47 +235,377 @@ impl IDatabase for DatabaseLocal {
         Ok(item)
     }
 
+    // All-or-nothing, like TransactWriteItems: every condition is checked
+    // before any item is changed
     async fn write(&self, items: Vec<TransactWriteItem>) -> Result<(), LogicError> {
+        let mut tables = self.tables.write().unwrap();
+        let mut pending: Vec<PendingWrite> = vec![];
         for item in items {
-            self.write_single(item).await?;
+            let write = self.prepare(&tables, item)?;
+            let duplicate = pending
+                .iter()
+                .any(|other| other.table_name == write.table_name && other.key == write.key);
+            if duplicate {
+                return Err(LogicError::ValidationError(
+                    "Transaction request cannot include multiple operations on one item"
+                        .to_string(),
+                ));
+            }
+            pending.push(write);
         }
-        Ok(())
+        let mut entries = vec![];
+        for write in pending {
+            match write.change {
+                Change::Put(item) => {
+                    entries.push(JournalEntry::put(&write.table_name, &write.key, &item));
+                    let items = tables.entry(write.table_name).or_default();
+                    items.insert(write.key, item);
+                }
+                Change::Delete => {
+                    entries.push(JournalEntry::delete(&write.table_name, &write.key));
+                    if let Some(items) = tables.get_mut(&write.table_name) {
+                        items.remove(&write.key);
+                    }
+                }
+                Change::None => {}
+            }
+        }
+        self.journal(&tables, &entries)
     }
 
     async fn write_single(&self, item: TransactWriteItem) -> Result<(), LogicError> {
-        if let Some(put) = item.put {
-            self.write_put(put)?;
-        } else if let Some(delete) = item.delete {
-            self.write_delete(delete)?;
-        } else {
-            return Err(LogicError::DatabaseError(
-                "Only Put/Delete is supported".to_string(),
-            ));
-        }
-        Ok(())
+        self.write(vec![item]).await
     }
 
-    async fn query(
-        &self,
-        query: QueryInputBuilder,
-    ) -> Result<Vec<HashMap<String, AttributeValue>>, LogicError> {
-        let hash_map = self.table.read().unwrap();
+    async fn query(&self, query: QueryInputBuilder) -> Result<QueryPage, LogicError> {
         let built = query
             .build()
             .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
-        let values = built
-            .expression_attribute_values()
-            .ok_or(LogicError::DatabaseError(
-                "No expression attribute values".to_string(),
-            ))?;
-        let requested_room_id = parse_attribute_value::<String>(values.get(":room_id"))?;
-        let mut results = vec![];
-        for (_, value) in hash_map.iter() {
-            let room_id = parse_attribute_value::<String>(value.hash_map.get("room_id"))?;
-            if room_id == requested_room_id {
-                results.push(value.hash_map.clone());
+        let table_name = built.table_name().unwrap_or_default();
+        let schema = self.schema(table_name);
+        let query = PreparedQuery::new(schema, &built)?;
+        let tables = self.tables.read().unwrap();
+        let Some(items) = tables.get(table_name) else {
+            return Ok(QueryPage::default());
+        };
+        query.page(schema, items, self.max_page_bytes)
+    }
+
+    async fn scan(&self, scan: ScanInputBuilder) -> Result<QueryPage, LogicError> {
+        let built = scan
+            .build()
+            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        let table_name = built.table_name().unwrap_or_default();
+        let schema = self.schema(table_name);
+        let tables = self.tables.read().unwrap();
+        let Some(items) = tables.get(table_name) else {
+            return Ok(QueryPage::default());
+        };
+        scan_page(schema, &built, items, self.max_page_bytes)
+    }
+}
+
+pub fn default_schemas() -> Vec<TableSchema> {
+    vec![WebsocketTable::schema(), RateLimitTable::schema()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::websocket_table::WebsocketTable;
+    use crate::domain::websocket_record::WebsocketRecord;
+    use aws_sdk_dynamodb::types::{AttributeValue, ConditionCheck, Put, Update};
+    use std::sync::Arc;
+
+    fn key(id: &str) -> Item {
+        HashMap::from([("id".to_string(), AttributeValue::S(id.to_string()))])
+    }
+
+    fn put(id: &str, condition: Option<&str>) -> TransactWriteItem {
+        let put = Put::builder()
+            .table_name("table")
+            .set_item(Some(key(id)))
+            .set_condition_expression(condition.map(str::to_string))
+            .build()
+            .unwrap();
+        TransactWriteItem::builder().put(put).build()
+    }
+
+    fn increment(id: &str, expected: &str) -> TransactWriteItem {
+        let update = Update::builder()
+            .table_name("table")
+            .set_key(Some(key(id)))
+            .update_expression("SET version = if_not_exists(version, :zero) + :one")
+            .condition_expression("attribute_not_exists(id) OR version = :expected")
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":expected", AttributeValue::N(expected.to_string()))
+            .build()
+            .unwrap();
+        TransactWriteItem::builder().update(update).build()
+    }
+
+    async fn read(db: &DatabaseLocal, id: &str) -> Result<Option<Item>, LogicError> {
+        let get = aws_sdk_dynamodb::types::Get::builder()
+            .table_name("table")
+            .set_key(Some(key(id)))
+            .build()
+            .unwrap();
+        let response = db
+            .read_single(TransactGetItem::builder().get(get).build())
+            .await?;
+        Ok(response.item)
+    }
+
+    async fn messages() -> Result<DatabaseLocal, LogicError> {
+        let schema = TableSchema::new("messages", KeySchema::composite("room_id", "sent_at"))
+            .with_index("user_index", KeySchema::composite("user_id", "sent_at"));
+        let db = DatabaseLocal::with_schemas(vec![schema]).await;
+        for (room_id, sent_at, user_id) in [
+            ("lobby", "3", Some("bob")),
+            ("lobby", "1", Some("alice")),
+            ("lobby", "2", None),
+            ("other", "1", Some("bob")),
+        ] {
+            let mut item = HashMap::from([
+                (
+                    "room_id".to_string(),
+                    AttributeValue::S(room_id.to_string()),
+                ),
+                (
+                    "sent_at".to_string(),
+                    AttributeValue::N(sent_at.to_string()),
+                ),
+            ]);
+            if let Some(user_id) = user_id {
+                item.insert(
+                    "user_id".to_string(),
+                    AttributeValue::S(user_id.to_string()),
+                );
             }
+            let put = Put::builder()
+                .table_name("messages")
+                .set_item(Some(item))
+                .build()
+                .unwrap();
+            db.write_single(TransactWriteItem::builder().put(put).build())
+                .await?;
+        }
+        Ok(db)
+    }
+
+    fn sent_at(items: &[Item]) -> Vec<String> {
+        items
+            .iter()
+            .map(|item| item["sent_at"].as_n().unwrap().clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_queries_by_key_condition_in_range_key_order() -> Result<(), LogicError> {
+        let db = messages().await?;
+        let query = QueryInputBuilder::default()
+            .table_name("messages")
+            .key_condition_expression("#room = :room AND sent_at BETWEEN :from AND :to")
+            .expression_attribute_names("#room", "room_id")
+            .expression_attribute_values(":room", AttributeValue::S("lobby".to_string()))
+            .expression_attribute_values(":from", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":to", AttributeValue::N("3".to_string()));
+        assert_eq!(
+            sent_at(&db.query(query.clone()).await?.items),
+            ["1", "2", "3"]
+        );
+
+        let newest = query.clone().scan_index_forward(false).limit(2);
+        assert_eq!(sent_at(&db.query(newest).await?.items), ["3", "2"]);
+
+        let filtered = query
+            .filter_expression("attribute_exists(user_id)")
+            .limit(2);
+        assert_eq!(sent_at(&db.query(filtered).await?.items), ["1"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queries_registered_indexes_only() -> Result<(), LogicError> {
+        let db = messages().await?;
+        let query = QueryInputBuilder::default()
+            .table_name("messages")
+            .index_name("user_index")
+            .key_condition_expression("user_id = :user")
+            .expression_attribute_values(":user", AttributeValue::S("bob".to_string()));
+        let items = db.query(query.clone()).await?.items;
+        assert_eq!(sent_at(&items), ["1", "3"]);
+
+        let unknown = query.clone().index_name("room_index");
+        assert!(db.query(unknown).await.is_err());
+        let not_a_key = query.key_condition_expression("room_id = :user");
+        assert!(db.query(not_a_key).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_pages_resume_after_last_evaluated_key() -> Result<(), LogicError> {
+        let db = messages().await?;
+        let query = QueryInputBuilder::default()
+            .table_name("messages")
+            .key_condition_expression("room_id = :room")
+            .expression_attribute_values(":room", AttributeValue::S("lobby".to_string()))
+            .scan_index_forward(false)
+            .limit(2);
+        let first = db.query(query.clone()).await?;
+        assert_eq!(sent_at(&first.items), ["3", "2"]);
+        let start_key = first.last_evaluated_key.unwrap();
+        assert_eq!(start_key["sent_at"], AttributeValue::N("2".to_string()));
+
+        let next = query.set_exclusive_start_key(Some(start_key));
+        let second = db.query(next).await?;
+        assert_eq!(sent_at(&second.items), ["1"]);
+        assert!(second.last_evaluated_key.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_room_connections_are_read_across_pages() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await.with_max_page_bytes(500));
+        for i in 0..25 {
+            let mut record = WebsocketRecord::new_with_room(&format!("id{}", i), "room");
+            WebsocketTable::to_db(&mut record, &db).await?;
+        }
+        let query = QueryInputBuilder::default()
+            .table_name("")
+            .index_name("room_id_index")
+            .key_condition_expression("room_id = :room_id")
+            .expression_attribute_values(":room_id", AttributeValue::S("room".to_string()));
+        assert!(db.query(query).await?.last_evaluated_key.is_some());
+        let records = WebsocketTable::get_room_connections("room", &db).await?;
+        assert_eq!(records.len(), 25);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_items_are_invisible() -> Result<(), LogicError> {
+        let schema = TableSchema::new("table", KeySchema::hash("id"))
+            .with_index("room_id_index", KeySchema::hash("room_id"))
+            .with_ttl("ttl");
+        let db = DatabaseLocal::with_schemas(vec![schema]).await;
+        let now = chrono::Utc::now().timestamp();
+        for (id, ttl) in [("live", now + 60), ("expired", now - 1)] {
+            let mut item = key(id);
+            item.insert("room_id".to_string(), AttributeValue::S("room".to_string()));
+            item.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
+            let put = Put::builder()
+                .table_name("table")
+                .set_item(Some(item))
+                .build()
+                .unwrap();
+            db.write_single(TransactWriteItem::builder().put(put).build())
+                .await?;
+        }
+        assert!(read(&db, "live").await?.is_some());
+        assert!(read(&db, "expired").await?.is_none());
+        let query = QueryInputBuilder::default()
+            .table_name("table")
+            .index_name("room_id_index")
+            .key_condition_expression("room_id = :room_id")
+            .expression_attribute_values(":room_id", AttributeValue::S("room".to_string()));
+        let page = db.query(query).await?;
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0]["id"], AttributeValue::S("live".to_string()));
+        // An expired item no longer exists as far as conditions are concerned
+        db.write_single(put("expired", Some("attribute_not_exists(id)")))
+            .await?;
+        assert!(read(&db, "expired").await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_condition_cancels_whole_transaction() -> Result<(), LogicError> {
+        let db = DatabaseLocal::new().await;
+        db.write_single(put("id1", None)).await?;
+        let check = ConditionCheck::builder()
+            .table_name("table")
+            .set_key(Some(key("id1")))
+            .condition_expression("attribute_not_exists(id)")
+            .build()
+            .unwrap();
+        let result = db
+            .write(vec![
+                put("id2", None),
+                TransactWriteItem::builder().condition_check(check).build(),
+            ])
+            .await;
+        assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+        assert!(read(&db, "id2").await?.is_none());
+
+        let result = db
+            .write_single(put("id1", Some("attribute_not_exists(id)")))
+            .await;
+        assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_checks_version() -> Result<(), LogicError> {
+        let db = DatabaseLocal::new().await;
+        db.write_single(increment("id1", "0")).await?;
+        db.write_single(increment("id1", "1")).await?;
+        let stale = db.write_single(increment("id1", "1")).await;
+        assert!(matches!(stale, Err(LogicError::ConditionalCheckFailed(_))));
+        let item = read(&db, "id1").await?.unwrap();
+        assert_eq!(item["version"], AttributeValue::N("2".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_two_operations_on_one_item() -> Result<(), LogicError> {
+        let db = DatabaseLocal::new().await;
+        let result = db
+            .write(vec![put("id1", None), increment("id1", "0")])
+            .await;
+        assert!(matches!(result, Err(LogicError::ValidationError(_))));
+        assert!(read(&db, "id1").await?.is_none());
+        Ok(())
+    }
+
+    fn temporary_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(uuid::Uuid::new_v4().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_journal_is_replayed_on_restart() -> Result<(), LogicError> {
+        let dir = temporary_dir();
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::with_persistence(&dir).await?);
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id1", "room"), &db).await?;
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id2", "room"), &db).await?;
+        let record = WebsocketTable::from_db("id2", &db).await?;
+        db.write_single(WebsocketTable::delete(&record)?).await?;
+        drop(db);
+
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::with_persistence(&dir).await?);
+        let records = WebsocketTable::get_room_connections("room", &db).await?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "id1");
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_compacts_journal() -> Result<(), LogicError> {
+        let dir = temporary_dir();
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::with_persistence(&dir).await?);
+        for i in 0..SNAPSHOT_EVERY + 1 {
+            let mut record = WebsocketRecord::new_with_room(&format!("id{}", i), "room");
+            WebsocketTable::to_db(&mut record, &db).await?;
         }
-        Ok(results)
+        drop(db);
+        let journal = std::fs::read_to_string(dir.join("journal.jsonl")).unwrap();
+        assert_eq!(journal.lines().count(), 1);
+
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::with_persistence(&dir).await?);
+        let records = WebsocketTable::get_room_connections("room", &db).await?;
+        assert_eq!(records.len(), SNAPSHOT_EVERY + 1);
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
     }
 }
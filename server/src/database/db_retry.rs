@@ -0,0 +1,273 @@
+#![allow(dead_code)]
+use super::db_trait::{IDatabase, QueryPage};
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::operation::query::builders::QueryInputBuilder;
+use aws_sdk_dynamodb::operation::scan::builders::ScanInputBuilder;
+use aws_sdk_dynamodb::types::{ItemResponse, TransactGetItem, TransactWriteItem};
+use axum::async_trait;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorClass {
+    Throttled,
+    ConditionalCheckFailed,
+    TransactionConflict,
+    Validation,
+    NotFound,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn of(error: &LogicError) -> Self {
+        match error {
+            LogicError::Throttled(_) => ErrorClass::Throttled,
+            LogicError::ConditionalCheckFailed(_) => ErrorClass::ConditionalCheckFailed,
+            LogicError::TransactionConflict(_) => ErrorClass::TransactionConflict,
+            LogicError::ValidationError(_) => ErrorClass::Validation,
+            LogicError::ResourceNotFound(_) => ErrorClass::NotFound,
+            _ => ErrorClass::Other,
+        }
+    }
+
+    // Throttled and conflicting requests were not applied, so they are safe
+    // to send again. A failed condition needs the caller to re-read first.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            ErrorClass::Throttled | ErrorClass::TransactionConflict
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    // Including the first attempt
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(25),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // "Full jitter": anywhere up to the exponential delay, so that clients
+    // throttled together do not all retry together
+    fn delay(&self, retry: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(retry));
+        let ceiling = exponential.min(self.max_delay);
+        ceiling.mul_f64(fastrand::f64())
+    }
+}
+
+// Wraps any database, retrying throttled and conflicting requests
+pub struct DatabaseRetry {
+    inner: Arc<dyn IDatabase>,
+    policy: RetryPolicy,
+}
+
+impl DatabaseRetry {
+    pub fn new(inner: Arc<dyn IDatabase>) -> Self {
+        Self::with_policy(inner, RetryPolicy::default())
+    }
+
+    pub fn with_policy(inner: Arc<dyn IDatabase>, policy: RetryPolicy) -> Self {
+        DatabaseRetry { inner, policy }
+    }
+
+    async fn with_retries<T, F, Fut>(&self, operation: &str, call: F) -> Result<T, LogicError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, LogicError>>,
+    {
+        let mut retries = 0;
+        loop {
+            let error = match call().await {
+                Ok(value) => {
+                    if retries > 0 {
+                        tracing::info!(operation, retries, "database call succeeded after retries");
+                    }
+                    return Ok(value);
+                }
+                Err(error) => error,
+            };
+            let class = ErrorClass::of(&error);
+            if !class.is_retriable() || retries + 1 >= self.policy.max_attempts {
+                if class.is_retriable() {
+                    tracing::warn!(operation, retries, error = %error, "database call gave up");
+                }
+                return Err(error);
+            }
+            let delay = self.policy.delay(retries);
+            retries += 1;
+            tracing::info!(
+                operation,
+                retries,
+                class = ?class,
+                delay_ms = delay.as_millis() as u64,
+                "retrying database call"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait]
+impl IDatabase for DatabaseRetry {
+    async fn read_single(&self, item: TransactGetItem) -> Result<ItemResponse, LogicError> {
+        self.with_retries("read_single", || self.inner.read_single(item.clone()))
+            .await
+    }
+
+    async fn write(&self, items: Vec<TransactWriteItem>) -> Result<(), LogicError> {
+        self.with_retries("write", || self.inner.write(items.clone()))
+            .await
+    }
+
+    async fn write_single(&self, item: TransactWriteItem) -> Result<(), LogicError> {
+        self.with_retries("write_single", || self.inner.write_single(item.clone()))
+            .await
+    }
+
+    async fn query(&self, query: QueryInputBuilder) -> Result<QueryPage, LogicError> {
+        self.with_retries("query", || self.inner.query(query.clone()))
+            .await
+    }
+
+    async fn scan(&self, scan: ScanInputBuilder) -> Result<QueryPage, LogicError> {
+        self.with_retries("scan", || self.inner.scan(scan.clone()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tracing_test::traced_test;
+
+    // Fails with each of `errors` in turn, then succeeds
+    struct Flaky {
+        errors: Mutex<Vec<LogicError>>,
+        calls: Mutex<u32>,
+    }
+
+    impl Flaky {
+        fn new(mut errors: Vec<LogicError>) -> Arc<Self> {
+            errors.reverse();
+            Arc::new(Flaky {
+                errors: Mutex::new(errors),
+                calls: Mutex::new(0),
+            })
+        }
+
+        fn calls(&self) -> u32 {
+            *self.calls.lock().unwrap()
+        }
+
+        fn next(&self) -> Result<(), LogicError> {
+            *self.calls.lock().unwrap() += 1;
+            match self.errors.lock().unwrap().pop() {
+                Some(error) => Err(error),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IDatabase for Flaky {
+        async fn read_single(&self, _: TransactGetItem) -> Result<ItemResponse, LogicError> {
+            self.next().map(|_| ItemResponse::builder().build())
+        }
+
+        async fn write(&self, _: Vec<TransactWriteItem>) -> Result<(), LogicError> {
+            self.next()
+        }
+
+        async fn write_single(&self, _: TransactWriteItem) -> Result<(), LogicError> {
+            self.next()
+        }
+
+        async fn query(&self, _: QueryInputBuilder) -> Result<QueryPage, LogicError> {
+            self.next().map(|_| QueryPage::default())
+        }
+
+        async fn scan(&self, _: ScanInputBuilder) -> Result<QueryPage, LogicError> {
+            self.next().map(|_| QueryPage::default())
+        }
+    }
+
+    fn retrying(inner: Arc<Flaky>) -> DatabaseRetry {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        DatabaseRetry::with_policy(inner, policy)
+    }
+
+    fn throttled() -> LogicError {
+        LogicError::Throttled("slow down".to_string())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_retries_throttles_and_conflicts() -> Result<(), LogicError> {
+        let inner = Flaky::new(vec![
+            throttled(),
+            LogicError::TransactionConflict("busy".to_string()),
+        ]);
+        let db = retrying(inner.clone());
+        db.write_single(TransactWriteItem::builder().build())
+            .await?;
+        assert_eq!(inner.calls(), 3);
+        assert!(logs_contain("retries=2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_other_errors() {
+        for error in [
+            LogicError::ConditionalCheckFailed("stale".to_string()),
+            LogicError::ValidationError("bad".to_string()),
+            LogicError::ResourceNotFound("no table".to_string()),
+            LogicError::DatabaseError("broken".to_string()),
+        ] {
+            let message = error.to_string();
+            let inner = Flaky::new(vec![error]);
+            let result = retrying(inner.clone())
+                .query(QueryInputBuilder::default())
+                .await;
+            assert_eq!(result.unwrap_err().to_string(), message);
+            assert_eq!(inner.calls(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let inner = Flaky::new((0..10).map(|_| throttled()).collect());
+        let result = retrying(inner.clone())
+            .scan(ScanInputBuilder::default())
+            .await;
+        assert_eq!(result.unwrap_err(), throttled());
+        assert_eq!(inner.calls(), RetryPolicy::default().max_attempts);
+    }
+
+    #[test]
+    fn test_delay_is_jittered_below_cap() {
+        let policy = RetryPolicy::default();
+        for retry in 0..20 {
+            let delay = policy.delay(retry);
+            assert!(delay <= policy.max_delay);
+            assert!(delay <= policy.base_delay * 2u32.saturating_pow(retry));
+        }
+    }
+}
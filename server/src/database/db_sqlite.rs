@@ -0,0 +1,625 @@
+#![allow(dead_code)]
+use super::attribute_value_json::{item_from_json, item_to_json};
+use super::db_local::default_schemas;
+use super::db_trait::{IDatabase, QueryPage};
+use super::expression::{apply_update, evaluate_condition, ExpressionContext, Item};
+use super::local_query::{now, scan_page, PreparedQuery, MAX_PAGE_BYTES};
+use super::table_schema::{KeySchema, TableSchema};
+use crate::domain::{errors::LogicError, vec_utils};
+use aws_sdk_dynamodb::operation::query::{builders::QueryInputBuilder, QueryInput};
+use aws_sdk_dynamodb::operation::scan::{builders::ScanInputBuilder, ScanInput};
+use aws_sdk_dynamodb::operation::transact_get_items::builders::TransactGetItemsOutputBuilder;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, Get, ItemResponse, TransactGetItem, TransactWriteItem,
+};
+use axum::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Expired rows are deleted when the database is opened and after this many
+// writes. Until then, reads treat them as already deleted.
+const PURGE_EVERY: usize = 1000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS items (
+        table_name TEXT NOT NULL,
+        id TEXT NOT NULL,
+        item TEXT NOT NULL,
+        PRIMARY KEY (table_name, id)
+    );
+    CREATE TABLE IF NOT EXISTS index_entries (
+        table_name TEXT NOT NULL,
+        index_name TEXT NOT NULL,
+        hash_key TEXT NOT NULL,
+        id TEXT NOT NULL,
+        PRIMARY KEY (table_name, index_name, id)
+    );
+    CREATE INDEX IF NOT EXISTS index_entries_by_hash_key
+        ON index_entries (table_name, index_name, hash_key);
+";
+
+// Items are stored as DynamoDB JSON under their primary storage key, and keyed
+// as declared by each table's TableSchema. Secondary indexes are maintained in
+// index_entries, keyed by the value of the index's hash key attribute.
+// SQLite calls block, so they run on tokio's blocking threads rather than on
+// the workers serving connections.
+pub struct DatabaseSqlite {
+    store: Arc<SqliteStore>,
+}
+
+impl DatabaseSqlite {
+    pub async fn new(path: &str) -> Result<Self, LogicError> {
+        Self::with_schemas(path, default_schemas()).await
+    }
+
+    pub async fn with_schemas(path: &str, schemas: Vec<TableSchema>) -> Result<Self, LogicError> {
+        let path = path.to_string();
+        let store = run_blocking(move || SqliteStore::open(&path, schemas)).await?;
+        Ok(DatabaseSqlite {
+            store: Arc::new(store),
+        })
+    }
+
+    async fn run<T, F>(&self, work: F) -> Result<T, LogicError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SqliteStore) -> Result<T, LogicError> + Send + 'static,
+    {
+        let store = self.store.clone();
+        run_blocking(move || work(&store)).await
+    }
+}
+
+struct SqliteStore {
+    connection: Mutex<Connection>,
+    schemas: HashMap<String, TableSchema>,
+    // Used for tables without a registered schema
+    fallback_schema: TableSchema,
+    writes_since_purge: AtomicUsize,
+}
+
+impl SqliteStore {
+    fn open(path: &str, schemas: Vec<TableSchema>) -> Result<Self, LogicError> {
+        let connection = Connection::open(path).map_err(to_database_error)?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(to_database_error)?;
+        let schemas = schemas
+            .into_iter()
+            .map(|schema| (schema.table_name.clone(), schema))
+            .collect();
+        let store = SqliteStore {
+            connection: Mutex::new(connection),
+            schemas,
+            fallback_schema: TableSchema::new("", KeySchema::hash("id")),
+            writes_since_purge: AtomicUsize::new(0),
+        };
+        store.purge_expired()?;
+        Ok(store)
+    }
+
+    fn schema(&self, table_name: &str) -> &TableSchema {
+        self.schemas
+            .get(table_name)
+            .unwrap_or(&self.fallback_schema)
+    }
+
+    // Deletes every row whose TTL has passed, along with its index entries
+    fn purge_expired(&self) -> Result<(), LogicError> {
+        let connection = self.connection.lock().unwrap();
+        for schema in self.schemas.values() {
+            let Some(ttl_attribute) = &schema.ttl_attribute else {
+                continue;
+            };
+            let path = format!("$.\"{}\".N", ttl_attribute);
+            let expired = "SELECT id FROM items WHERE table_name = ?1
+                 AND CAST(json_extract(item, ?2) AS REAL) <= ?3";
+            connection
+                .execute(
+                    &format!(
+                        "DELETE FROM index_entries WHERE table_name = ?1 AND id IN ({})",
+                        expired
+                    ),
+                    params![schema.table_name, path, now()],
+                )
+                .map_err(to_database_error)?;
+            connection
+                .execute(
+                    &format!(
+                        "DELETE FROM items WHERE table_name = ?1 AND id IN ({})",
+                        expired
+                    ),
+                    params![schema.table_name, path, now()],
+                )
+                .map_err(to_database_error)?;
+        }
+        Ok(())
+    }
+
+    // Stores the whole item and rewrites its index entries
+    fn store_item(
+        &self,
+        connection: &Connection,
+        table_name: &str,
+        id: &str,
+        item: &Item,
+    ) -> Result<(), LogicError> {
+        let json = item_to_json(item).to_string();
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO items (table_name, id, item) VALUES (?1, ?2, ?3)",
+                params![table_name, id, json],
+            )
+            .map_err(to_database_error)?;
+        connection
+            .execute(
+                "DELETE FROM index_entries WHERE table_name = ?1 AND id = ?2",
+                params![table_name, id],
+            )
+            .map_err(to_database_error)?;
+        for (index_name, key) in &self.schema(table_name).indexes {
+            // Like DynamoDB, items without the index keys are left out of the index
+            if !key.attributes().all(|name| item.contains_key(name)) {
+                continue;
+            }
+            let Some(hash_key) = item.get(&key.hash_key).and_then(key_string) else {
+                continue;
+            };
+            connection
+                .execute(
+                    "INSERT INTO index_entries (table_name, index_name, hash_key, id)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![table_name, index_name, hash_key, id],
+                )
+                .map_err(to_database_error)?;
+        }
+        Ok(())
+    }
+
+    fn remove_item(
+        &self,
+        connection: &Connection,
+        table_name: &str,
+        id: &str,
+    ) -> Result<(), LogicError> {
+        connection
+            .execute(
+                "DELETE FROM items WHERE table_name = ?1 AND id = ?2",
+                params![table_name, id],
+            )
+            .map_err(to_database_error)?;
+        connection
+            .execute(
+                "DELETE FROM index_entries WHERE table_name = ?1 AND id = ?2",
+                params![table_name, id],
+            )
+            .map_err(to_database_error)?;
+        Ok(())
+    }
+
+    // Like DynamoDB once its TTL sweep has run, an expired item is treated as
+    // already deleted
+    fn read_live(
+        &self,
+        connection: &Connection,
+        table_name: &str,
+        id: &str,
+    ) -> Result<Option<Item>, LogicError> {
+        let item = read_item(connection, table_name, id)?;
+        let schema = self.schema(table_name);
+        Ok(item.filter(|item| !schema.is_expired(item, now())))
+    }
+
+    // The table and primary storage key that a write operates on
+    fn write_target(&self, item: &TransactWriteItem) -> Result<(String, String), LogicError> {
+        let (table_name, key) = if let Some(put) = &item.put {
+            (&put.table_name, &put.item)
+        } else if let Some(delete) = &item.delete {
+            (&delete.table_name, &delete.key)
+        } else if let Some(update) = &item.update {
+            (&update.table_name, &update.key)
+        } else if let Some(check) = &item.condition_check {
+            (&check.table_name, &check.key)
+        } else {
+            return Err(LogicError::DatabaseError(
+                "Expected a Put, Delete, Update or ConditionCheck".to_string(),
+            ));
+        };
+        let id = self.schema(table_name).primary_key.storage_key(key)?;
+        Ok((table_name.clone(), id))
+    }
+
+    // Conditions are evaluated inside the write transaction, so a failed
+    // condition rolls back the items written before it
+    fn write_item(
+        &self,
+        transaction: &Transaction,
+        item: TransactWriteItem,
+        id: &str,
+    ) -> Result<(), LogicError> {
+        if let Some(put) = item.put {
+            let context = ExpressionContext::new(
+                put.expression_attribute_names.as_ref(),
+                put.expression_attribute_values.as_ref(),
+            );
+            let current = self.read_live(transaction, &put.table_name, id)?;
+            check_condition(
+                put.condition_expression.as_deref(),
+                &context,
+                current.as_ref(),
+                id,
+            )?;
+            self.store_item(transaction, &put.table_name, id, &put.item)
+        } else if let Some(delete) = item.delete {
+            let context = ExpressionContext::new(
+                delete.expression_attribute_names.as_ref(),
+                delete.expression_attribute_values.as_ref(),
+            );
+            let current = self.read_live(transaction, &delete.table_name, id)?;
+            check_condition(
+                delete.condition_expression.as_deref(),
+                &context,
+                current.as_ref(),
+                id,
+            )?;
+            self.remove_item(transaction, &delete.table_name, id)
+        } else if let Some(update) = item.update {
+            let context = ExpressionContext::new(
+                update.expression_attribute_names.as_ref(),
+                update.expression_attribute_values.as_ref(),
+            );
+            let current = self.read_live(transaction, &update.table_name, id)?;
+            check_condition(
+                update.condition_expression.as_deref(),
+                &context,
+                current.as_ref(),
+                id,
+            )?;
+            // Like DynamoDB, updating a missing item creates it from its key
+            let mut updated = current.unwrap_or(update.key);
+            apply_update(&update.update_expression, &context, &mut updated)?;
+            self.store_item(transaction, &update.table_name, id, &updated)
+        } else if let Some(check) = item.condition_check {
+            let context = ExpressionContext::new(
+                check.expression_attribute_names.as_ref(),
+                check.expression_attribute_values.as_ref(),
+            );
+            let current = self.read_live(transaction, &check.table_name, id)?;
+            check_condition(
+                Some(&check.condition_expression),
+                &context,
+                current.as_ref(),
+                id,
+            )
+        } else {
+            Err(LogicError::DatabaseError(
+                "Expected a Put, Delete, Update or ConditionCheck".to_string(),
+            ))
+        }
+    }
+
+    fn read(&self, get: Get) -> Result<Option<Item>, LogicError> {
+        let id = self
+            .schema(&get.table_name)
+            .primary_key
+            .storage_key(&get.key)?;
+        let connection = self.connection.lock().unwrap();
+        self.read_live(&connection, &get.table_name, &id)
+    }
+
+    fn write(&self, items: Vec<TransactWriteItem>) -> Result<(), LogicError> {
+        {
+            let mut connection = self.connection.lock().unwrap();
+            let transaction = connection.transaction().map_err(to_database_error)?;
+            let mut targets = HashSet::new();
+            for item in items {
+                let (table_name, id) = self.write_target(&item)?;
+                if !targets.insert((table_name, id.clone())) {
+                    return Err(LogicError::ValidationError(
+                        "Transaction request cannot include multiple operations on one item"
+                            .to_string(),
+                    ));
+                }
+                // Dropping the transaction on error rolls back every item
+                self.write_item(&transaction, item, &id)?;
+            }
+            transaction.commit().map_err(to_database_error)?;
+        }
+        if self.writes_since_purge.fetch_add(1, Ordering::Relaxed) + 1 >= PURGE_EVERY {
+            self.writes_since_purge.store(0, Ordering::Relaxed);
+            self.purge_expired()?;
+        }
+        Ok(())
+    }
+
+    // Only the items under the queried hash key are read from SQLite. The key
+    // condition, order and paging are then applied as DatabaseLocal does.
+    fn query(&self, built: QueryInput) -> Result<QueryPage, LogicError> {
+        let table_name = built.table_name().unwrap_or_default();
+        let schema = self.schema(table_name);
+        let query = PreparedQuery::new(schema, &built)?;
+        let hash_key = key_string(&query.hash_value).ok_or(LogicError::DatabaseError(
+            "The hash key must be a string or number".to_string(),
+        ))?;
+        let connection = self.connection.lock().unwrap();
+        let rows = match built.index_name() {
+            Some(index_name) => select_rows(
+                &connection,
+                "SELECT items.id, items.item FROM index_entries
+                 JOIN items ON items.table_name = index_entries.table_name
+                     AND items.id = index_entries.id
+                 WHERE index_entries.table_name = ?1
+                     AND index_entries.index_name = ?2
+                     AND index_entries.hash_key = ?3",
+                params![table_name, index_name, hash_key],
+            )?,
+            // Storage keys start with the hash key, followed by the unit
+            // separator if the table has a range key
+            None => select_rows(
+                &connection,
+                "SELECT id, item FROM items
+                 WHERE table_name = ?1 AND (id = ?2 OR (id > ?3 AND id < ?4))",
+                params![
+                    table_name,
+                    hash_key,
+                    format!("{}\u{1f}", hash_key),
+                    format!("{}\u{20}", hash_key)
+                ],
+            )?,
+        };
+        query.page(
+            schema,
+            rows.iter().map(|(id, item)| (id, item)),
+            MAX_PAGE_BYTES,
+        )
+    }
+
+    fn scan(&self, built: ScanInput) -> Result<QueryPage, LogicError> {
+        let table_name = built.table_name().unwrap_or_default();
+        let schema = self.schema(table_name);
+        let connection = self.connection.lock().unwrap();
+        let rows = select_rows(
+            &connection,
+            "SELECT id, item FROM items WHERE table_name = ?1",
+            params![table_name],
+        )?;
+        scan_page(
+            schema,
+            &built,
+            rows.iter().map(|(id, item)| (id, item)),
+            MAX_PAGE_BYTES,
+        )
+    }
+}
+
+#[async_trait]
+impl IDatabase for DatabaseSqlite {
+    async fn read_single(&self, item: TransactGetItem) -> Result<ItemResponse, LogicError> {
+        let get = item.get.ok_or(LogicError::DatabaseError(
+            "Only Gets are supported".to_string(),
+        ))?;
+        let item = self.run(move |store| store.read(get)).await?;
+
+        let item_response = ItemResponse::builder().set_item(item).build();
+        let output = TransactGetItemsOutputBuilder::default()
+            .responses(item_response)
+            .build();
+        let items = output
+            .responses
+            .ok_or(LogicError::DatabaseError("No response".to_string()))?;
+        let item =
+            vec_utils::single(items).map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        Ok(item)
+    }
+
+    async fn write(&self, items: Vec<TransactWriteItem>) -> Result<(), LogicError> {
+        self.run(move |store| store.write(items)).await
+    }
+
+    async fn write_single(&self, item: TransactWriteItem) -> Result<(), LogicError> {
+        self.write(vec![item]).await
+    }
+
+    async fn query(&self, query: QueryInputBuilder) -> Result<QueryPage, LogicError> {
+        let built = query
+            .build()
+            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        self.run(move |store| store.query(built)).await
+    }
+
+    async fn scan(&self, scan: ScanInputBuilder) -> Result<QueryPage, LogicError> {
+        let built = scan
+            .build()
+            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        self.run(move |store| store.scan(built)).await
+    }
+}
+
+async fn run_blocking<T, F>(work: F) -> Result<T, LogicError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, LogicError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| LogicError::DatabaseError(e.to_string()))?
+}
+
+// Key attributes are strings or numbers, and are stored as their text
+fn key_string(value: &AttributeValue) -> Option<&String> {
+    match value {
+        AttributeValue::S(value) | AttributeValue::N(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn select_rows(
+    connection: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<(String, Item)>, LogicError> {
+    let mut statement = connection.prepare(sql).map_err(to_database_error)?;
+    let rows: Vec<(String, String)> = statement
+        .query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(to_database_error)?
+        .collect::<Result<_, _>>()
+        .map_err(to_database_error)?;
+    rows.into_iter()
+        .map(|(id, json)| Ok((id, parse_item(&json)?)))
+        .collect()
+}
+
+fn read_item(
+    connection: &Connection,
+    table_name: &str,
+    id: &str,
+) -> Result<Option<Item>, LogicError> {
+    let json: Option<String> = connection
+        .query_row(
+            "SELECT item FROM items WHERE table_name = ?1 AND id = ?2",
+            params![table_name, id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(to_database_error)?;
+    json.map(|json| parse_item(&json)).transpose()
+}
+
+fn check_condition(
+    expression: Option<&str>,
+    context: &ExpressionContext,
+    item: Option<&Item>,
+    id: &str,
+) -> Result<(), LogicError> {
+    let Some(expression) = expression else {
+        return Ok(());
+    };
+    if evaluate_condition(expression, context, item)? {
+        Ok(())
+    } else {
+        Err(LogicError::ConditionalCheckFailed(format!(
+            "Transaction cancelled, the conditional request failed for {}",
+            id
+        )))
+    }
+}
+
+fn parse_item(json: &str) -> Result<Item, LogicError> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    item_from_json(&value)
+}
+
+fn to_database_error(e: rusqlite::Error) -> LogicError {
+    LogicError::DatabaseError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{rate_limit_table::RateLimitTable, websocket_table::WebsocketTable};
+    use crate::domain::rate_limit::{RateLimit, TokenBucket};
+    use crate::domain::websocket_record::WebsocketRecord;
+    use chrono::SubsecRound;
+
+    #[tokio::test]
+    async fn test_records_survive_reopening() -> Result<(), LogicError> {
+        let path = std::env::temp_dir().join(format!("{}.sqlite", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(path).await?);
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id1", "room"), &db).await?;
+        drop(db);
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(path).await?);
+        let record = WebsocketTable::from_db("id1", &db).await?;
+        assert_eq!(record.room_id, "room");
+        std::fs::remove_file(path).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_rows_are_purged_on_open() -> Result<(), LogicError> {
+        let path = std::env::temp_dir().join(format!("{}.sqlite", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(path).await?);
+        let mut expired = WebsocketRecord::new_with_room("id1", "room");
+        expired.ttl = now() - 1;
+        WebsocketTable::to_db(&mut expired, &db).await?;
+        WebsocketTable::to_db(&mut WebsocketRecord::new_with_room("id2", "room"), &db).await?;
+        drop(db);
+        let db = DatabaseSqlite::new(path).await?;
+        let connection = db.store.connection.lock().unwrap();
+        let count = |table: &str| -> i64 {
+            let sql = format!("SELECT COUNT(*) FROM {}", table);
+            connection.query_row(&sql, [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("items"), 1);
+        assert_eq!(count("index_entries"), 1);
+        drop(connection);
+        std::fs::remove_file(path).unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_buckets_round_trip() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(":memory:").await?);
+        let limit = RateLimit {
+            capacity: 2.0,
+            refill_per_second: 1.0,
+        };
+        // Empty, so that it is not expired as soon as it is written
+        let bucket = TokenBucket {
+            tokens: 0.0,
+            updated_at: chrono::Utc::now().trunc_subsecs(0),
+        };
+        let buckets = [(bucket.clone(), limit)];
+        RateLimitTable::to_db([("key", &buckets[0])], &db).await?;
+        assert_eq!(RateLimitTable::from_db("key", &db).await?, Some(bucket));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queries_room_id_index() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(":memory:").await?);
+        db.write(vec![
+            WebsocketTable::save(&WebsocketRecord::new_with_room("id1", "room"))?,
+            WebsocketTable::save(&WebsocketRecord::new_with_room("id2", "room"))?,
+            WebsocketTable::save(&WebsocketRecord::new("id3"))?,
+        ])
+        .await?;
+        let mut record = WebsocketTable::from_db("id2", &db).await?;
+        record.room_id = "other".to_string();
+        WebsocketTable::to_db(&mut record, &db).await?;
+        let records = WebsocketTable::get_room_connections("room", &db).await?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "id1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_transaction_is_rolled_back() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(":memory:").await?);
+        let result = db
+            .write(vec![
+                WebsocketTable::save(&WebsocketRecord::new("id1"))?,
+                TransactWriteItem::builder().build(),
+            ])
+            .await;
+        assert!(result.is_err());
+        assert!(WebsocketTable::from_db("id1", &db).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_version_is_rejected() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseSqlite::new(":memory:").await?);
+        WebsocketTable::to_db(&mut WebsocketRecord::new("id1"), &db).await?;
+        let mut first = WebsocketTable::from_db("id1", &db).await?;
+        let mut second = first.clone();
+        WebsocketTable::to_db(&mut first, &db).await?;
+        let result = WebsocketTable::to_db(&mut second, &db).await;
+        assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+        assert_eq!(WebsocketTable::from_db("id1", &db).await?.version, 2);
+        Ok(())
+    }
+}
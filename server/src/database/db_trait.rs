@@ -2,18 +2,24 @@ use std::collections::HashMap;
 
 use crate::domain::errors::LogicError;
 use aws_sdk_dynamodb::{
-    operation::query::builders::QueryInputBuilder,
+    operation::{query::builders::QueryInputBuilder, scan::builders::ScanInputBuilder},
     types::{AttributeValue, ItemResponse, TransactGetItem, TransactWriteItem},
 };
 use axum::async_trait;
 
+// One page of query or scan results. Pass last_evaluated_key as the exclusive start
+// key of the next query to read the following page.
+#[derive(Clone, Debug, Default)]
+pub struct QueryPage {
+    pub items: Vec<HashMap<String, AttributeValue>>,
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+}
+
 #[async_trait]
 pub trait IDatabase: Send + Sync {
     async fn read_single(&self, item: TransactGetItem) -> Result<ItemResponse, LogicError>;
     async fn write(&self, items: Vec<TransactWriteItem>) -> Result<(), LogicError>;
     async fn write_single(&self, item: TransactWriteItem) -> Result<(), LogicError>;
-    async fn query(
-        &self,
-        query: QueryInputBuilder,
-    ) -> Result<Vec<HashMap<String, AttributeValue>>, LogicError>;
+    async fn query(&self, query: QueryInputBuilder) -> Result<QueryPage, LogicError>;
+    async fn scan(&self, scan: ScanInputBuilder) -> Result<QueryPage, LogicError>;
 }
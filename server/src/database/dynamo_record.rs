@@ -0,0 +1,250 @@
+#![allow(dead_code)]
+use super::attribute_value_parser::format_datetime;
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::primitives::Blob;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+// Implemented with #[derive(DynamoRecord)], which reads every field with
+// AttributeValueParser and writes it with ToAttributeValue
+pub trait DynamoRecord: Sized {
+    fn to_item(&self) -> HashMap<String, AttributeValue>;
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Result<Self, LogicError>;
+}
+
+// None leaves the attribute out of the item
+pub trait ToAttributeValue {
+    fn to_attribute_value(&self) -> Option<AttributeValue>;
+}
+
+impl ToAttributeValue for String {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        Some(AttributeValue::S(self.clone()))
+    }
+}
+
+macro_rules! impl_number_value {
+    ($($number:ty),*) => {
+        $(
+            impl ToAttributeValue for $number {
+                fn to_attribute_value(&self) -> Option<AttributeValue> {
+                    Some(AttributeValue::N(self.to_string()))
+                }
+            }
+        )*
+    };
+}
+
+impl_number_value!(i32, i64, u64, f64);
+
+impl ToAttributeValue for bool {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        Some(AttributeValue::Bool(*self))
+    }
+}
+
+impl ToAttributeValue for DateTime<Utc> {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        Some(AttributeValue::S(format_datetime(self)))
+    }
+}
+
+impl<T: ToAttributeValue> ToAttributeValue for Option<T> {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        self.as_ref().and_then(T::to_attribute_value)
+    }
+}
+
+impl ToAttributeValue for Uuid {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        Some(AttributeValue::S(self.to_string()))
+    }
+}
+
+impl ToAttributeValue for Blob {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        Some(AttributeValue::B(self.clone()))
+    }
+}
+
+// A None element keeps its place in the list as NULL
+impl<T: ToAttributeValue> ToAttributeValue for Vec<T> {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        let list = self
+            .iter()
+            .map(|value| {
+                value
+                    .to_attribute_value()
+                    .unwrap_or(AttributeValue::Null(true))
+            })
+            .collect();
+        Some(AttributeValue::L(list))
+    }
+}
+
+impl<T: ToAttributeValue> ToAttributeValue for HashMap<String, T> {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        let map = self
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.to_attribute_value()?)))
+            .collect();
+        Some(AttributeValue::M(map))
+    }
+}
+
+// DynamoDB rejects empty sets, so an empty set leaves the attribute out
+impl ToAttributeValue for HashSet<String> {
+    fn to_attribute_value(&self) -> Option<AttributeValue> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(AttributeValue::Ss(self.iter().cloned().collect()))
+    }
+}
+
+macro_rules! impl_number_set_value {
+    ($($number:ty),*) => {
+        $(
+            impl ToAttributeValue for HashSet<$number> {
+                fn to_attribute_value(&self) -> Option<AttributeValue> {
+                    if self.is_empty() {
+                        return None;
+                    }
+                    Some(AttributeValue::Ns(self.iter().map(|n| n.to_string()).collect()))
+                }
+            }
+        )*
+    };
+}
+
+impl_number_set_value!(i32, i64, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_record_derive::{DynamoRecord, DynamoValue};
+
+    #[derive(Debug, Default, DynamoRecord, PartialEq)]
+    struct Profile {
+        nickname: String,
+        #[dynamo(default)]
+        verified: bool,
+    }
+
+    fn default_score() -> f64 {
+        1.0
+    }
+
+    #[derive(Debug, DynamoRecord, PartialEq)]
+    struct Player {
+        id: String,
+        #[dynamo(rename = "room")]
+        room_id: String,
+        nickname: Option<String>,
+        #[dynamo(default = "default_score")]
+        score: f64,
+        profile: Profile,
+        joined_at: DateTime<Utc>,
+    }
+
+    #[test]
+    fn test_round_trips_record() -> Result<(), LogicError> {
+        let player = Player {
+            id: "id1".to_string(),
+            room_id: "lobby".to_string(),
+            nickname: None,
+            score: 2.5,
+            profile: Profile {
+                nickname: "bob".to_string(),
+                verified: true,
+            },
+            joined_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let item = player.to_item();
+        assert_eq!(item["room"], AttributeValue::S("lobby".to_string()));
+        assert!(!item.contains_key("room_id"));
+        assert!(!item.contains_key("nickname"));
+        assert!(matches!(item["profile"], AttributeValue::M(_)));
+        assert_eq!(
+            item["joined_at"],
+            AttributeValue::S("2023-11-14T22:13:20.000000Z".to_string())
+        );
+        assert_eq!(Player::from_item(&item)?, player);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_attributes_use_defaults() -> Result<(), LogicError> {
+        let mut item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("id1".to_string())),
+            ("room".to_string(), AttributeValue::S("lobby".to_string())),
+            (
+                "joined_at".to_string(),
+                AttributeValue::S("2024-01-01T00:00:00.000000Z".to_string()),
+            ),
+            (
+                "profile".to_string(),
+                AttributeValue::M(HashMap::from([(
+                    "nickname".to_string(),
+                    AttributeValue::S("bob".to_string()),
+                )])),
+            ),
+        ]);
+        let player = Player::from_item(&item)?;
+        assert_eq!(player.score, 1.0);
+        assert!(!player.profile.verified);
+        item.remove("room");
+        assert!(Player::from_item(&item).is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, DynamoValue, PartialEq)]
+    enum Role {
+        Host,
+        #[dynamo(rename = "guest")]
+        Guest,
+    }
+
+    #[derive(Debug, DynamoRecord, PartialEq)]
+    struct Seat {
+        id: Uuid,
+        role: Role,
+        visits: u64,
+        history: Vec<i64>,
+        labels: HashMap<String, String>,
+        tags: HashSet<String>,
+        lucky_numbers: HashSet<i32>,
+        avatar: Blob,
+    }
+
+    #[test]
+    fn test_round_trips_enums_and_collections() -> Result<(), LogicError> {
+        let seat = Seat {
+            id: Uuid::new_v4(),
+            role: Role::Guest,
+            visits: u64::MAX,
+            history: vec![-1, 2],
+            labels: HashMap::from([("colour".to_string(), "red".to_string())]),
+            tags: HashSet::new(),
+            lucky_numbers: HashSet::from([7, 13]),
+            avatar: Blob::new(vec![0, 1, 2]),
+        };
+        let mut item = seat.to_item();
+        assert_eq!(item["role"], AttributeValue::S("guest".to_string()));
+        assert!(!item.contains_key("tags"));
+        assert_eq!(Seat::from_item(&item)?, seat);
+        assert_eq!(
+            Role::Host.to_attribute_value(),
+            Some(AttributeValue::S("Host".to_string()))
+        );
+
+        item.insert("role".to_string(), AttributeValue::S("Admin".to_string()));
+        assert_eq!(
+            Seat::from_item(&item).unwrap_err().to_string(),
+            "[DatabaseError] Attribute 'role': unknown variant 'Admin'"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1018 @@
+#![allow(dead_code)]
+use super::attribute_value_parser::type_name;
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Evaluates DynamoDB condition and update expressions against items held in
+// memory, so local databases reject and apply writes the way DynamoDB does.
+// See https://docs.aws.amazon.com/amazondynamodb/latest/developerguide/Expressions.html
+
+pub type Item = HashMap<String, AttributeValue>;
+
+// The placeholders an expression may refer to, e.g. #name and :value
+#[derive(Clone, Copy, Default)]
+pub struct ExpressionContext<'a> {
+    pub names: Option<&'a HashMap<String, String>>,
+    pub values: Option<&'a HashMap<String, AttributeValue>>,
+}
+
+impl<'a> ExpressionContext<'a> {
+    pub fn new(
+        names: Option<&'a HashMap<String, String>>,
+        values: Option<&'a HashMap<String, AttributeValue>>,
+    ) -> Self {
+        ExpressionContext { names, values }
+    }
+}
+
+// A missing item is evaluated as an item without attributes, so
+// attribute_not_exists(id) holds for it
+pub fn evaluate_condition(
+    expression: &str,
+    context: &ExpressionContext,
+    item: Option<&Item>,
+) -> Result<bool, LogicError> {
+    Ok(ConditionExpression::parse(expression, context)?.evaluate(item))
+}
+
+// A parsed condition, for evaluating the same expression against many items
+pub struct ConditionExpression {
+    condition: Condition,
+}
+
+impl ConditionExpression {
+    pub fn parse(expression: &str, context: &ExpressionContext) -> Result<Self, LogicError> {
+        let mut parser = Parser::new(expression, context)?;
+        let condition = parser.condition()?;
+        parser.expect_end()?;
+        Ok(ConditionExpression { condition })
+    }
+
+    pub fn evaluate(&self, item: Option<&Item>) -> bool {
+        let empty = Item::new();
+        self.condition.evaluate(item.unwrap_or(&empty))
+    }
+}
+
+pub fn apply_update(
+    expression: &str,
+    context: &ExpressionContext,
+    item: &mut Item,
+) -> Result<(), LogicError> {
+    let mut parser = Parser::new(expression, context)?;
+    let actions = parser.update()?;
+    // Every operand refers to the item as it was before the update
+    let original = item.clone();
+    let mut resolved = vec![];
+    for action in actions {
+        let value = match &action {
+            UpdateAction::Set(_, value) => Some(value.evaluate(&original)?),
+            UpdateAction::Add(_, value) | UpdateAction::Delete(_, value) => Some(value.clone()),
+            UpdateAction::Remove(_) => None,
+        };
+        resolved.push((action, value));
+    }
+    for (action, value) in resolved {
+        match (action, value) {
+            (UpdateAction::Set(path, _), Some(value)) => set_path(item, &path, value)?,
+            (UpdateAction::Remove(path), _) => remove_path(item, &path),
+            (UpdateAction::Add(path, _), Some(value)) => {
+                let updated = match get_path(item, &path) {
+                    None => value,
+                    Some(current) => add(current, &value)?,
+                };
+                set_path(item, &path, updated)?;
+            }
+            (UpdateAction::Delete(path, _), Some(value)) => {
+                let Some(current) = get_path(item, &path) else {
+                    continue;
+                };
+                match subtract_set(current, &value)? {
+                    Some(remaining) => set_path(item, &path, remaining)?,
+                    None => remove_path(item, &path),
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// DynamoDB only accepts an equality on the hash key, optionally followed by
+// AND one comparison, BETWEEN or begins_with on the range key. Returns the
+// value the hash key must equal.
+pub fn check_key_condition(
+    expression: &str,
+    context: &ExpressionContext,
+    hash_key: &str,
+    range_key: Option<&str>,
+) -> Result<AttributeValue, LogicError> {
+    let mut parser = Parser::new(expression, context)?;
+    let condition = parser.condition()?;
+    parser.expect_end()?;
+    let parts = match condition {
+        Condition::And(left, right) => vec![*left, *right],
+        condition => vec![condition],
+    };
+    let mut hash_value = None;
+    let mut has_range_key = false;
+    for part in &parts {
+        let (name, equals) = key_attribute(part)
+            .ok_or(invalid_expression(expression, "unsupported key condition"))?;
+        match equals {
+            Some(value) if name == hash_key && hash_value.is_none() => {
+                hash_value = Some(value.clone());
+            }
+            _ if Some(name) == range_key && !has_range_key => has_range_key = true,
+            _ => {
+                return Err(invalid_expression(
+                    expression,
+                    &format!("{} is not a key that can be queried here", name),
+                ))
+            }
+        }
+    }
+    hash_value.ok_or(invalid_expression(
+        expression,
+        &format!("expected {} = :value", hash_key),
+    ))
+}
+
+// The key attribute a part of a key condition refers to, and the value it
+// must equal if it is an equality
+fn key_attribute(condition: &Condition) -> Option<(&str, Option<&AttributeValue>)> {
+    let (path, equals) = match condition {
+        Condition::Compare(Operand::Path(path), operator, Operand::Value(value))
+            if *operator != "<>" =>
+        {
+            (path, (*operator == "=").then_some(value))
+        }
+        Condition::Between(Operand::Path(path), Operand::Value(_), Operand::Value(_)) => {
+            (path, None)
+        }
+        Condition::Function(name, arguments) if name == "begins_with" => match arguments.as_slice()
+        {
+            [Operand::Path(path), Operand::Value(_)] => (path, None),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match path.as_slice() {
+        [PathElement::Key(name)] => Some((name.as_str(), equals)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Name(String),
+    Value(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 14] = [
+    "<>", "<=", ">=", "=", "<", ">", "(", ")", ",", ".", "[", "]", "+", "-",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, LogicError> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if c == '#' || c == ':' || is_word(c) {
+            let start = if is_word(c) { 0 } else { 1 };
+            let end = rest[start..]
+                .find(|c: char| !is_word(c))
+                .map_or(rest.len(), |i| i + start);
+            let word = rest[..end].to_string();
+            tokens.push(match c {
+                '#' => Token::Name(word),
+                ':' => Token::Value(word),
+                _ => Token::Word(word),
+            });
+            rest = &rest[end..];
+        } else {
+            return Err(invalid_expression(
+                expression,
+                &format!("unexpected '{}'", c),
+            ));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PathElement {
+    Key(String),
+    Index(usize),
+}
+
+type Path = Vec<PathElement>;
+
+#[derive(Debug)]
+enum Operand {
+    Path(Path),
+    Value(AttributeValue),
+    Size(Path),
+}
+
+impl Operand {
+    fn resolve(&self, item: &Item) -> Option<AttributeValue> {
+        match self {
+            Operand::Path(path) => get_path(item, path).cloned(),
+            Operand::Value(value) => Some(value.clone()),
+            Operand::Size(path) => size(get_path(item, path)?),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Condition {
+    Compare(Operand, &'static str, Operand),
+    Between(Operand, Operand, Operand),
+    In(Operand, Vec<Operand>),
+    Function(String, Vec<Operand>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    // Like DynamoDB, any comparison with a missing attribute is false
+    fn evaluate(&self, item: &Item) -> bool {
+        match self {
+            Condition::Compare(left, operator, right) => {
+                let (Some(left), Some(right)) = (left.resolve(item), right.resolve(item)) else {
+                    return false;
+                };
+                match *operator {
+                    "=" => equals(&left, &right),
+                    "<>" => !equals(&left, &right),
+                    _ => compare(&left, &right).is_some_and(|ordering| match *operator {
+                        "<" => ordering.is_lt(),
+                        "<=" => ordering.is_le(),
+                        ">" => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    }),
+                }
+            }
+            Condition::Between(value, low, high) => {
+                let (Some(value), Some(low), Some(high)) =
+                    (value.resolve(item), low.resolve(item), high.resolve(item))
+                else {
+                    return false;
+                };
+                compare(&value, &low).is_some_and(Ordering::is_ge)
+                    && compare(&value, &high).is_some_and(Ordering::is_le)
+            }
+            Condition::In(value, candidates) => {
+                let Some(value) = value.resolve(item) else {
+                    return false;
+                };
+                candidates
+                    .iter()
+                    .filter_map(|candidate| candidate.resolve(item))
+                    .any(|candidate| equals(&value, &candidate))
+            }
+            Condition::Function(name, arguments) => evaluate_function(name, arguments, item),
+            Condition::And(left, right) => left.evaluate(item) && right.evaluate(item),
+            Condition::Or(left, right) => left.evaluate(item) || right.evaluate(item),
+            Condition::Not(condition) => !condition.evaluate(item),
+        }
+    }
+}
+
+fn evaluate_function(name: &str, arguments: &[Operand], item: &Item) -> bool {
+    let path_exists = || match arguments.first() {
+        Some(Operand::Path(path)) => get_path(item, path).is_some(),
+        _ => false,
+    };
+    match name {
+        "attribute_exists" => path_exists(),
+        "attribute_not_exists" => !path_exists(),
+        _ => {
+            let (Some(first), Some(second)) = (
+                arguments.first().and_then(|a| a.resolve(item)),
+                arguments.get(1).and_then(|a| a.resolve(item)),
+            ) else {
+                return false;
+            };
+            match (name, &first, &second) {
+                ("attribute_type", value, AttributeValue::S(kind)) => type_name(value) == kind,
+                ("begins_with", AttributeValue::S(value), AttributeValue::S(prefix)) => {
+                    value.starts_with(prefix.as_str())
+                }
+                ("begins_with", AttributeValue::B(value), AttributeValue::B(prefix)) => {
+                    value.as_ref().starts_with(prefix.as_ref())
+                }
+                ("contains", AttributeValue::S(value), AttributeValue::S(part)) => {
+                    value.contains(part.as_str())
+                }
+                ("contains", AttributeValue::Ss(set), AttributeValue::S(member)) => {
+                    set.contains(member)
+                }
+                ("contains", AttributeValue::Ns(set), AttributeValue::N(member)) => {
+                    set.iter().any(|n| equals_number(n, member))
+                }
+                ("contains", AttributeValue::Bs(set), AttributeValue::B(member)) => {
+                    set.contains(member)
+                }
+                ("contains", AttributeValue::L(list), member) => {
+                    list.iter().any(|value| equals(value, member))
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SetValue {
+    Operand(Operand),
+    IfNotExists(Path, Box<SetValue>),
+    ListAppend(Box<SetValue>, Box<SetValue>),
+    Plus(Box<SetValue>, Box<SetValue>),
+    Minus(Box<SetValue>, Box<SetValue>),
+}
+
+impl SetValue {
+    fn evaluate(&self, item: &Item) -> Result<AttributeValue, LogicError> {
+        match self {
+            SetValue::Operand(operand) => operand.resolve(item).ok_or(LogicError::DatabaseError(
+                "The provided expression refers to an attribute that does not exist in the item"
+                    .to_string(),
+            )),
+            SetValue::IfNotExists(path, default) => match get_path(item, path) {
+                Some(value) => Ok(value.clone()),
+                None => default.evaluate(item),
+            },
+            SetValue::ListAppend(left, right) => {
+                match (left.evaluate(item)?, right.evaluate(item)?) {
+                    (AttributeValue::L(mut left), AttributeValue::L(right)) => {
+                        left.extend(right);
+                        Ok(AttributeValue::L(left))
+                    }
+                    _ => Err(LogicError::DatabaseError(
+                        "list_append expects two lists".to_string(),
+                    )),
+                }
+            }
+            SetValue::Plus(left, right) => {
+                arithmetic(&left.evaluate(item)?, &right.evaluate(item)?, 1)
+            }
+            SetValue::Minus(left, right) => {
+                arithmetic(&left.evaluate(item)?, &right.evaluate(item)?, -1)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum UpdateAction {
+    Set(Path, SetValue),
+    Remove(Path),
+    Add(Path, AttributeValue),
+    Delete(Path, AttributeValue),
+}
+
+const FUNCTIONS: [&str; 5] = [
+    "attribute_exists",
+    "attribute_not_exists",
+    "attribute_type",
+    "begins_with",
+    "contains",
+];
+
+const CLAUSES: [&str; 4] = ["SET", "REMOVE", "ADD", "DELETE"];
+
+struct Parser<'a> {
+    expression: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    context: &'a ExpressionContext<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(expression: &'a str, context: &'a ExpressionContext<'a>) -> Result<Self, LogicError> {
+        Ok(Parser {
+            expression,
+            tokens: tokenize(expression)?,
+            position: 0,
+            context,
+        })
+    }
+
+    fn error(&self, reason: &str) -> LogicError {
+        invalid_expression(self.expression, reason)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_symbol(&mut self, symbol: &'static str) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<(), LogicError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", symbol)))
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), LogicError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(self.error(&format!("unexpected {:?}", token))),
+        }
+    }
+
+    fn is_call(&self, names: &[&str]) -> bool {
+        let is_name = names.iter().any(|name| self.peek_keyword(name));
+        is_name && self.tokens.get(self.position + 1) == Some(&Token::Symbol("("))
+    }
+
+    fn name(&self, placeholder: &str) -> Result<String, LogicError> {
+        self.context
+            .names
+            .and_then(|names| names.get(placeholder))
+            .cloned()
+            .ok_or(self.error(&format!("{} is not defined", placeholder)))
+    }
+
+    fn value(&self, placeholder: &str) -> Result<AttributeValue, LogicError> {
+        self.context
+            .values
+            .and_then(|values| values.get(placeholder))
+            .cloned()
+            .ok_or(self.error(&format!("{} is not defined", placeholder)))
+    }
+
+    fn path(&mut self) -> Result<Path, LogicError> {
+        let mut path = vec![PathElement::Key(self.path_key()?)];
+        loop {
+            if self.eat_symbol(".") {
+                path.push(PathElement::Key(self.path_key()?));
+            } else if self.eat_symbol("[") {
+                let index = match self.next() {
+                    Some(Token::Word(word)) => word.parse::<usize>().ok(),
+                    _ => None,
+                };
+                path.push(PathElement::Index(
+                    index.ok_or(self.error("expected a list index"))?,
+                ));
+                self.expect_symbol("]")?;
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn path_key(&mut self) -> Result<String, LogicError> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(Token::Name(name)) => self.name(&name),
+            _ => Err(self.error("expected an attribute name")),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, LogicError> {
+        if self.is_call(&["size"]) {
+            self.position += 2;
+            let path = self.path()?;
+            self.expect_symbol(")")?;
+            return Ok(Operand::Size(path));
+        }
+        if let Some(Token::Value(placeholder)) = self.peek().cloned() {
+            self.position += 1;
+            return Ok(Operand::Value(self.value(&placeholder)?));
+        }
+        Ok(Operand::Path(self.path()?))
+    }
+
+    fn operands(&mut self) -> Result<Vec<Operand>, LogicError> {
+        self.expect_symbol("(")?;
+        let mut operands = vec![self.operand()?];
+        while self.eat_symbol(",") {
+            operands.push(self.operand()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(operands)
+    }
+
+    fn condition(&mut self) -> Result<Condition, LogicError> {
+        let mut condition = self.conjunction()?;
+        while self.eat_keyword("OR") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.conjunction()?));
+        }
+        Ok(condition)
+    }
+
+    fn conjunction(&mut self) -> Result<Condition, LogicError> {
+        let mut condition = self.negation()?;
+        while self.eat_keyword("AND") {
+            condition = Condition::And(Box::new(condition), Box::new(self.negation()?));
+        }
+        Ok(condition)
+    }
+
+    fn negation(&mut self) -> Result<Condition, LogicError> {
+        if self.eat_keyword("NOT") {
+            return Ok(Condition::Not(Box::new(self.negation()?)));
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<Condition, LogicError> {
+        if self.eat_symbol("(") {
+            let condition = self.condition()?;
+            self.expect_symbol(")")?;
+            return Ok(condition);
+        }
+        if self.is_call(&FUNCTIONS) {
+            let Some(Token::Word(name)) = self.next() else {
+                return Err(self.error("expected a function"));
+            };
+            let name = name.to_lowercase();
+            let arguments = self.operands()?;
+            let expected = if name.starts_with("attribute_") && name != "attribute_type" {
+                1
+            } else {
+                2
+            };
+            if arguments.len() != expected {
+                return Err(self.error(&format!("{} expects {} arguments", name, expected)));
+            }
+            if !matches!(arguments.first(), Some(Operand::Path(_))) {
+                return Err(self.error(&format!("{} expects a path first", name)));
+            }
+            return Ok(Condition::Function(name, arguments));
+        }
+        let left = self.operand()?;
+        if self.eat_keyword("BETWEEN") {
+            let low = self.operand()?;
+            if !self.eat_keyword("AND") {
+                return Err(self.error("expected AND in BETWEEN"));
+            }
+            return Ok(Condition::Between(left, low, self.operand()?));
+        }
+        if self.eat_keyword("IN") {
+            return Ok(Condition::In(left, self.operands()?));
+        }
+        let operator = match self.next() {
+            Some(Token::Symbol(symbol)) if ["=", "<>", "<", "<=", ">", ">="].contains(&symbol) => {
+                symbol
+            }
+            _ => return Err(self.error("expected a comparison")),
+        };
+        Ok(Condition::Compare(left, operator, self.operand()?))
+    }
+
+    fn update(&mut self) -> Result<Vec<UpdateAction>, LogicError> {
+        let mut actions = vec![];
+        while self.peek().is_some() {
+            let clause = CLAUSES
+                .into_iter()
+                .find(|clause| self.eat_keyword(clause))
+                .ok_or(self.error("expected SET, REMOVE, ADD or DELETE"))?;
+            loop {
+                actions.push(self.update_action(clause)?);
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        if actions.is_empty() {
+            return Err(self.error("expected an update"));
+        }
+        Ok(actions)
+    }
+
+    fn update_action(&mut self, clause: &str) -> Result<UpdateAction, LogicError> {
+        let path = self.path()?;
+        match clause {
+            "SET" => {
+                self.expect_symbol("=")?;
+                Ok(UpdateAction::Set(path, self.set_value()?))
+            }
+            "REMOVE" => Ok(UpdateAction::Remove(path)),
+            _ => {
+                let Some(Token::Value(placeholder)) = self.next() else {
+                    return Err(self.error(&format!("{} expects a value", clause)));
+                };
+                let value = self.value(&placeholder)?;
+                if clause == "ADD" {
+                    Ok(UpdateAction::Add(path, value))
+                } else {
+                    Ok(UpdateAction::Delete(path, value))
+                }
+            }
+        }
+    }
+
+    fn set_value(&mut self) -> Result<SetValue, LogicError> {
+        let left = self.set_term()?;
+        if self.eat_symbol("+") {
+            return Ok(SetValue::Plus(Box::new(left), Box::new(self.set_term()?)));
+        }
+        if self.eat_symbol("-") {
+            return Ok(SetValue::Minus(Box::new(left), Box::new(self.set_term()?)));
+        }
+        Ok(left)
+    }
+
+    fn set_term(&mut self) -> Result<SetValue, LogicError> {
+        if self.is_call(&["if_not_exists"]) {
+            self.position += 2;
+            let path = self.path()?;
+            self.expect_symbol(",")?;
+            let default = self.set_value()?;
+            self.expect_symbol(")")?;
+            return Ok(SetValue::IfNotExists(path, Box::new(default)));
+        }
+        if self.is_call(&["list_append"]) {
+            self.position += 2;
+            let left = self.set_value()?;
+            self.expect_symbol(",")?;
+            let right = self.set_value()?;
+            self.expect_symbol(")")?;
+            return Ok(SetValue::ListAppend(Box::new(left), Box::new(right)));
+        }
+        Ok(SetValue::Operand(self.operand()?))
+    }
+}
+
+fn invalid_expression(expression: &str, reason: &str) -> LogicError {
+    LogicError::DatabaseError(format!("Invalid expression '{}': {}", expression, reason))
+}
+
+fn invalid_path(path: &Path) -> LogicError {
+    LogicError::DatabaseError(format!(
+        "The document path {:?} is invalid for update",
+        path
+    ))
+}
+
+fn get_path<'a>(item: &'a Item, path: &[PathElement]) -> Option<&'a AttributeValue> {
+    let (PathElement::Key(key), rest) = path.split_first()? else {
+        return None;
+    };
+    let mut current = item.get(key)?;
+    for element in rest {
+        current = match (element, current) {
+            (PathElement::Key(key), AttributeValue::M(map)) => map.get(key)?,
+            (PathElement::Index(index), AttributeValue::L(list)) => list.get(*index)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+fn get_path_mut<'a>(item: &'a mut Item, path: &[PathElement]) -> Option<&'a mut AttributeValue> {
+    let (PathElement::Key(key), rest) = path.split_first()? else {
+        return None;
+    };
+    let mut current = item.get_mut(key)?;
+    for element in rest {
+        current = match (element, current) {
+            (PathElement::Key(key), AttributeValue::M(map)) => map.get_mut(key)?,
+            (PathElement::Index(index), AttributeValue::L(list)) => list.get_mut(*index)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+fn set_path(item: &mut Item, path: &Path, value: AttributeValue) -> Result<(), LogicError> {
+    let (last, parent) = path.split_last().ok_or(invalid_path(path))?;
+    if parent.is_empty() {
+        let PathElement::Key(key) = last else {
+            return Err(invalid_path(path));
+        };
+        item.insert(key.clone(), value);
+        return Ok(());
+    }
+    match (last, get_path_mut(item, parent)) {
+        (PathElement::Key(key), Some(AttributeValue::M(map))) => {
+            map.insert(key.clone(), value);
+        }
+        // Setting past the end of a list appends, as in DynamoDB
+        (PathElement::Index(index), Some(AttributeValue::L(list))) => match list.get_mut(*index) {
+            Some(existing) => *existing = value,
+            None => list.push(value),
+        },
+        _ => return Err(invalid_path(path)),
+    }
+    Ok(())
+}
+
+fn remove_path(item: &mut Item, path: &Path) {
+    let Some((last, parent)) = path.split_last() else {
+        return;
+    };
+    if parent.is_empty() {
+        if let PathElement::Key(key) = last {
+            item.remove(key);
+        }
+        return;
+    }
+    match (last, get_path_mut(item, parent)) {
+        (PathElement::Key(key), Some(AttributeValue::M(map))) => {
+            map.remove(key);
+        }
+        (PathElement::Index(index), Some(AttributeValue::L(list))) if *index < list.len() => {
+            list.remove(*index);
+        }
+        _ => {}
+    }
+}
+
+fn size(value: &AttributeValue) -> Option<AttributeValue> {
+    let size = match value {
+        AttributeValue::S(s) => s.len(),
+        AttributeValue::B(b) => b.as_ref().len(),
+        AttributeValue::Ss(set) | AttributeValue::Ns(set) => set.len(),
+        AttributeValue::Bs(set) => set.len(),
+        AttributeValue::L(list) => list.len(),
+        AttributeValue::M(map) => map.len(),
+        _ => return None,
+    };
+    Some(AttributeValue::N(size.to_string()))
+}
+
+// Orders strings, numbers and binaries the way DynamoDB sorts range keys
+pub fn compare(left: &AttributeValue, right: &AttributeValue) -> Option<Ordering> {
+    match (left, right) {
+        (AttributeValue::S(left), AttributeValue::S(right)) => Some(left.cmp(right)),
+        (AttributeValue::N(left), AttributeValue::N(right)) => left
+            .parse::<f64>()
+            .ok()?
+            .partial_cmp(&right.parse::<f64>().ok()?),
+        (AttributeValue::B(left), AttributeValue::B(right)) => {
+            Some(left.as_ref().cmp(right.as_ref()))
+        }
+        _ => None,
+    }
+}
+
+fn equals(left: &AttributeValue, right: &AttributeValue) -> bool {
+    match (left, right) {
+        (AttributeValue::N(left), AttributeValue::N(right)) => equals_number(left, right),
+        _ => left == right,
+    }
+}
+
+fn equals_number(left: &str, right: &str) -> bool {
+    compare(
+        &AttributeValue::N(left.to_string()),
+        &AttributeValue::N(right.to_string()),
+    ) == Some(Ordering::Equal)
+}
+
+// Integers are added exactly; anything else falls back to floating point
+fn add_numbers(left: &str, right: &str, sign: i128) -> Result<String, LogicError> {
+    if let (Ok(left), Ok(right)) = (left.parse::<i128>(), right.parse::<i128>()) {
+        return Ok((left + sign * right).to_string());
+    }
+    match (left.parse::<f64>(), right.parse::<f64>()) {
+        (Ok(left), Ok(right)) => Ok((left + sign as f64 * right).to_string()),
+        _ => Err(LogicError::DatabaseError(format!(
+            "Cannot add {} and {}",
+            left, right
+        ))),
+    }
+}
+
+fn arithmetic(
+    left: &AttributeValue,
+    right: &AttributeValue,
+    sign: i128,
+) -> Result<AttributeValue, LogicError> {
+    match (left, right) {
+        (AttributeValue::N(left), AttributeValue::N(right)) => {
+            Ok(AttributeValue::N(add_numbers(left, right, sign)?))
+        }
+        _ => Err(LogicError::DatabaseError(
+            "An operand in the update expression has an incorrect data type".to_string(),
+        )),
+    }
+}
+
+fn union<T: Clone + PartialEq>(left: &[T], right: &[T]) -> Vec<T> {
+    let mut result = left.to_vec();
+    for value in right {
+        if !result.contains(value) {
+            result.push(value.clone());
+        }
+    }
+    result
+}
+
+fn add(current: &AttributeValue, value: &AttributeValue) -> Result<AttributeValue, LogicError> {
+    match (current, value) {
+        (AttributeValue::N(_), AttributeValue::N(_)) => arithmetic(current, value, 1),
+        (AttributeValue::Ss(left), AttributeValue::Ss(right)) => {
+            Ok(AttributeValue::Ss(union(left, right)))
+        }
+        (AttributeValue::Ns(left), AttributeValue::Ns(right)) => {
+            Ok(AttributeValue::Ns(union(left, right)))
+        }
+        (AttributeValue::Bs(left), AttributeValue::Bs(right)) => {
+            Ok(AttributeValue::Bs(union(left, right)))
+        }
+        _ => Err(LogicError::DatabaseError(
+            "ADD expects a number or a set of the same type".to_string(),
+        )),
+    }
+}
+
+// Returns None when nothing is left, since DynamoDB does not store empty sets
+fn subtract_set(
+    current: &AttributeValue,
+    value: &AttributeValue,
+) -> Result<Option<AttributeValue>, LogicError> {
+    fn difference<T: Clone + PartialEq>(left: &[T], right: &[T]) -> Vec<T> {
+        left.iter()
+            .filter(|v| !right.contains(v))
+            .cloned()
+            .collect()
+    }
+    let remaining = match (current, value) {
+        (AttributeValue::Ss(left), AttributeValue::Ss(right)) => {
+            AttributeValue::Ss(difference(left, right))
+        }
+        (AttributeValue::Ns(left), AttributeValue::Ns(right)) => {
+            AttributeValue::Ns(difference(left, right))
+        }
+        (AttributeValue::Bs(left), AttributeValue::Bs(right)) => {
+            AttributeValue::Bs(difference(left, right))
+        }
+        _ => {
+            return Err(LogicError::DatabaseError(
+                "DELETE expects a set of the same type".to_string(),
+            ))
+        }
+    };
+    let is_empty = match &remaining {
+        AttributeValue::Ss(set) | AttributeValue::Ns(set) => set.is_empty(),
+        AttributeValue::Bs(set) => set.is_empty(),
+        _ => false,
+    };
+    Ok((!is_empty).then_some(remaining))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(value: &str) -> AttributeValue {
+        AttributeValue::S(value.to_string())
+    }
+
+    fn n(value: &str) -> AttributeValue {
+        AttributeValue::N(value.to_string())
+    }
+
+    fn item() -> Item {
+        HashMap::from([
+            ("id".to_string(), s("id1")),
+            ("version".to_string(), n("3")),
+            ("room_id".to_string(), s("lobby")),
+            (
+                "tags".to_string(),
+                AttributeValue::Ss(vec!["a".to_string()]),
+            ),
+            (
+                "profile".to_string(),
+                AttributeValue::M(HashMap::from([("name".to_string(), s("bob"))])),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_evaluates_conditions() -> Result<(), LogicError> {
+        let names = HashMap::from([("#v".to_string(), "version".to_string())]);
+        let values = HashMap::from([
+            (":v".to_string(), n("3.0")),
+            (":low".to_string(), n("1")),
+            (":room".to_string(), s("lob")),
+            (":name".to_string(), s("bob")),
+        ]);
+        let context = ExpressionContext::new(Some(&names), Some(&values));
+        let item = item();
+        let holds = |expression| evaluate_condition(expression, &context, Some(&item));
+        assert!(holds("#v = :v")?);
+        assert!(holds(
+            "attribute_exists(id) AND attribute_not_exists(missing)"
+        )?);
+        assert!(holds("#v BETWEEN :low AND :v")?);
+        assert!(holds(
+            "begins_with(room_id, :room) AND profile.name = :name"
+        )?);
+        assert!(holds("NOT (#v < :low OR size(tags) > :low)")?);
+        assert!(holds("#v IN (:low, :v)")?);
+        assert!(!holds("missing = :v")?);
+        assert!(evaluate_condition(
+            "attribute_not_exists(id)",
+            &context,
+            None
+        )?);
+        assert!(holds("#unknown = :v").is_err());
+        assert!(holds("#v = ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_applies_updates() -> Result<(), LogicError> {
+        let values = HashMap::from([
+            (":one".to_string(), n("1")),
+            (":zero".to_string(), n("0")),
+            (":name".to_string(), s("alice")),
+            (
+                ":tags".to_string(),
+                AttributeValue::Ss(vec!["b".to_string()]),
+            ),
+            (":a".to_string(), AttributeValue::Ss(vec!["a".to_string()])),
+        ]);
+        let context = ExpressionContext::new(None, Some(&values));
+        let mut item = item();
+        apply_update(
+            "SET version = version + :one, profile.name = :name, \
+             visits = if_not_exists(visits, :zero) + :one \
+             REMOVE room_id ADD tags :tags",
+            &context,
+            &mut item,
+        )?;
+        assert_eq!(item["version"], n("4"));
+        assert_eq!(item["visits"], n("1"));
+        assert_eq!(
+            get_path(&item, &[key("profile"), key("name")]),
+            Some(&s("alice"))
+        );
+        assert!(!item.contains_key("room_id"));
+        assert_eq!(
+            item["tags"],
+            AttributeValue::Ss(vec!["a".to_string(), "b".to_string()])
+        );
+
+        apply_update("DELETE tags :a, tags :tags", &context, &mut item)?;
+        assert!(!item.contains_key("tags"));
+        assert!(apply_update("SET missing = missing + :one", &context, &mut item).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checks_key_conditions() -> Result<(), LogicError> {
+        let names = HashMap::from([("#at".to_string(), "sent_at".to_string())]);
+        let values = HashMap::from([
+            (":room".to_string(), s("lobby")),
+            (":t".to_string(), n("1")),
+        ]);
+        let context = ExpressionContext::new(Some(&names), Some(&values));
+        let check =
+            |expression| check_key_condition(expression, &context, "room_id", Some("sent_at"));
+        assert_eq!(check("room_id = :room")?, s("lobby"));
+        check("room_id = :room AND #at BETWEEN :t AND :t")?;
+        check("begins_with(#at, :t) AND room_id = :room")?;
+        assert!(check("#at > :t").is_err());
+        assert!(check("room_id > :room").is_err());
+        assert!(check("room_id = :room OR #at = :t").is_err());
+        assert!(check("room_id = :room AND name = :t").is_err());
+        Ok(())
+    }
+
+    fn key(name: &str) -> PathElement {
+        PathElement::Key(name.to_string())
+    }
+}
@@ -0,0 +1,173 @@
+#![allow(dead_code)]
+use super::attribute_value_json::{item_from_json, item_to_json};
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+pub type Item = HashMap<String, AttributeValue>;
+pub type Tables = HashMap<String, HashMap<String, Item>>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Put {
+        table: String,
+        key: String,
+        item: Value,
+    },
+    Delete {
+        table: String,
+        key: String,
+    },
+}
+
+impl JournalEntry {
+    pub fn put(table: &str, key: &str, item: &Item) -> Self {
+        JournalEntry::Put {
+            table: table.to_string(),
+            key: key.to_string(),
+            item: item_to_json(item),
+        }
+    }
+
+    pub fn delete(table: &str, key: &str) -> Self {
+        JournalEntry::Delete {
+            table: table.to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    fn apply(self, tables: &mut Tables) -> Result<(), LogicError> {
+        match self {
+            JournalEntry::Put { table, key, item } => {
+                let item = item_from_json(&item)?;
+                tables.entry(table).or_default().insert(key, item);
+            }
+            JournalEntry::Delete { table, key } => {
+                if let Some(items) = tables.get_mut(&table) {
+                    items.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// An append-only journal of writes, compacted into a snapshot every
+// `snapshot_every` entries. Entries are idempotent, so replaying a journal
+// that was not truncated after its snapshot gives the same state.
+pub struct LocalJournal {
+    dir: PathBuf,
+    file: File,
+    entries_since_snapshot: usize,
+    snapshot_every: usize,
+}
+
+impl LocalJournal {
+    pub fn open(dir: &Path, snapshot_every: usize) -> Result<(Self, Tables), LogicError> {
+        fs::create_dir_all(dir).map_err(to_database_error)?;
+        let mut tables = Self::read_snapshot(dir)?;
+        let entries = Self::read_journal(dir)?;
+        let entries_since_snapshot = entries.len();
+        for entry in entries {
+            entry.apply(&mut tables)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .map_err(to_database_error)?;
+        let journal = LocalJournal {
+            dir: dir.to_path_buf(),
+            file,
+            entries_since_snapshot,
+            snapshot_every,
+        };
+        Ok((journal, tables))
+    }
+
+    // Entries are written together, so a transaction is journaled by a single
+    // write. Returns true when a snapshot is due.
+    pub fn append(&mut self, entries: &[JournalEntry]) -> Result<bool, LogicError> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        self.file
+            .write_all(lines.as_bytes())
+            .map_err(to_database_error)?;
+        self.entries_since_snapshot += entries.len();
+        Ok(self.entries_since_snapshot >= self.snapshot_every)
+    }
+
+    pub fn write_snapshot(&mut self, tables: &Tables) -> Result<(), LogicError> {
+        let json: HashMap<&String, HashMap<&String, Value>> = tables
+            .iter()
+            .map(|(table, items)| {
+                let items = items
+                    .iter()
+                    .map(|(key, item)| (key, item_to_json(item)))
+                    .collect();
+                (table, items)
+            })
+            .collect();
+        let temporary = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        fs::write(&temporary, serde_json::to_string(&json)?).map_err(to_database_error)?;
+        fs::rename(&temporary, self.dir.join(SNAPSHOT_FILE)).map_err(to_database_error)?;
+        self.file.set_len(0).map_err(to_database_error)?;
+        self.entries_since_snapshot = 0;
+        Ok(())
+    }
+
+    fn read_snapshot(dir: &Path) -> Result<Tables, LogicError> {
+        let path = dir.join(SNAPSHOT_FILE);
+        if !path.exists() {
+            return Ok(Tables::new());
+        }
+        let contents = fs::read_to_string(path).map_err(to_database_error)?;
+        let json: HashMap<String, HashMap<String, Value>> = serde_json::from_str(&contents)?;
+        let mut tables = Tables::new();
+        for (table, items) in json {
+            let items = items
+                .into_iter()
+                .map(|(key, item)| Ok((key, item_from_json(&item)?)))
+                .collect::<Result<_, LogicError>>()?;
+            tables.insert(table, items);
+        }
+        Ok(tables)
+    }
+
+    fn read_journal(dir: &Path) -> Result<Vec<JournalEntry>, LogicError> {
+        let path = dir.join(JOURNAL_FILE);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let file = File::open(path).map_err(to_database_error)?;
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(to_database_error)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                // A crash mid-write can leave a partial final line
+                Err(e) => tracing::warn!("skipping unreadable journal entry: {}", e),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn to_database_error(e: std::io::Error) -> LogicError {
+    LogicError::DatabaseError(e.to_string())
+}
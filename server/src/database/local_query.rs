@@ -0,0 +1,220 @@
+use super::db_trait::QueryPage;
+use super::expression::{
+    check_key_condition, compare, ConditionExpression, ExpressionContext, Item,
+};
+use super::table_schema::{KeySchema, TableSchema};
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::operation::{query::QueryInput, scan::ScanInput};
+use aws_sdk_dynamodb::types::AttributeValue;
+use std::cmp::Ordering;
+
+// Reads pages of queries and scans from items held outside DynamoDB, so that
+// every local database orders, pages, filters and expires items the same way.
+
+// Like DynamoDB, a page also ends once 1MB has been read
+pub const MAX_PAGE_BYTES: usize = 1024 * 1024;
+
+pub fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+// A query checked against the table's keys. The items it can match all have
+// hash_value under the hash key of the queried index.
+pub struct PreparedQuery {
+    key: KeySchema,
+    pub hash_value: AttributeValue,
+    key_condition: ConditionExpression,
+    filter: Option<ConditionExpression>,
+    forward: bool,
+    exclusive_start_key: Option<Item>,
+    limit: Option<i32>,
+}
+
+impl PreparedQuery {
+    pub fn new(schema: &TableSchema, query: &QueryInput) -> Result<Self, LogicError> {
+        let key = schema.key_schema(query.index_name())?.clone();
+        let context = ExpressionContext::new(
+            query.expression_attribute_names(),
+            query.expression_attribute_values(),
+        );
+        let key_condition = query
+            .key_condition_expression()
+            .ok_or(LogicError::DatabaseError(
+                "A key condition expression is required".to_string(),
+            ))?;
+        let hash_value = check_key_condition(
+            key_condition,
+            &context,
+            &key.hash_key,
+            key.range_key.as_deref(),
+        )?;
+        let key_condition = ConditionExpression::parse(key_condition, &context)?;
+        let filter = query
+            .filter_expression()
+            .map(|filter| ConditionExpression::parse(filter, &context))
+            .transpose()?;
+        Ok(PreparedQuery {
+            key,
+            hash_value,
+            key_condition,
+            filter,
+            forward: query.scan_index_forward() != Some(false),
+            exclusive_start_key: query.exclusive_start_key().cloned(),
+            limit: query.limit(),
+        })
+    }
+
+    // `items` are the table's items by primary storage key, or any subset
+    // that includes every item with hash_value
+    pub fn page<'a>(
+        &self,
+        schema: &TableSchema,
+        items: impl IntoIterator<Item = (&'a String, &'a Item)>,
+        max_page_bytes: usize,
+    ) -> Result<QueryPage, LogicError> {
+        // Like DynamoDB indexes, items without the index keys are left out
+        let now = now();
+        let mut matches: Vec<(&String, &Item)> = items
+            .into_iter()
+            .filter(|(_, item)| !schema.is_expired(item, now))
+            .filter(|(_, item)| self.key.attributes().all(|name| item.contains_key(name)))
+            .filter(|(_, item)| self.key_condition.evaluate(Some(item)))
+            .collect();
+        // Sorted by range key, with the primary key keeping the order stable
+        let order = |left_key: &String, left: &Item, right_key: &String, right: &Item| {
+            let range = self
+                .key
+                .range_key
+                .as_ref()
+                .and_then(|range_key| compare(left.get(range_key)?, right.get(range_key)?))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| left_key.cmp(right_key));
+            if self.forward {
+                range
+            } else {
+                range.reverse()
+            }
+        };
+        matches.sort_by(|(left_key, left), (right_key, right)| {
+            order(left_key, left, right_key, right)
+        });
+        if let Some(start) = &self.exclusive_start_key {
+            let start_key = schema.primary_key.storage_key(start)?;
+            matches.retain(|(key, item)| order(key, item, &start_key, start).is_gt());
+        }
+
+        let has_more = truncate_page(&mut matches, self.limit, max_page_bytes);
+        let last_evaluated_key = match matches.last() {
+            Some((_, item)) if has_more => Some(key_of(
+                item,
+                schema.primary_key.attributes().chain(self.key.attributes()),
+            )),
+            _ => None,
+        };
+        let items = filter_page(matches, self.filter.as_ref());
+        Ok(QueryPage {
+            items,
+            last_evaluated_key,
+        })
+    }
+}
+
+// Items are scanned in primary key order
+pub fn scan_page<'a>(
+    schema: &TableSchema,
+    scan: &ScanInput,
+    items: impl IntoIterator<Item = (&'a String, &'a Item)>,
+    max_page_bytes: usize,
+) -> Result<QueryPage, LogicError> {
+    if scan.index_name().is_some() {
+        return Err(LogicError::DatabaseError(
+            "Scanning an index is not supported".to_string(),
+        ));
+    }
+    let context = ExpressionContext::new(
+        scan.expression_attribute_names(),
+        scan.expression_attribute_values(),
+    );
+    let filter = scan
+        .filter_expression()
+        .map(|filter| ConditionExpression::parse(filter, &context))
+        .transpose()?;
+
+    let now = now();
+    let mut matches: Vec<(&String, &Item)> = items
+        .into_iter()
+        .filter(|(_, item)| !schema.is_expired(item, now))
+        .collect();
+    matches.sort_by_key(|(key, _)| *key);
+    if let Some(start) = scan.exclusive_start_key() {
+        let start_key = schema.primary_key.storage_key(start)?;
+        matches.retain(|(key, _)| **key > start_key);
+    }
+    let has_more = truncate_page(&mut matches, scan.limit(), max_page_bytes);
+    let last_evaluated_key = match matches.last() {
+        Some((_, item)) if has_more => Some(key_of(item, schema.primary_key.attributes())),
+        _ => None,
+    };
+    let items = filter_page(matches, filter.as_ref());
+    Ok(QueryPage {
+        items,
+        last_evaluated_key,
+    })
+}
+
+// Like DynamoDB, a page ends at the limit or once max_page_bytes have been
+// read. Returns whether any matches were left out of the page.
+fn truncate_page(
+    matches: &mut Vec<(&String, &Item)>,
+    limit: Option<i32>,
+    max_page_bytes: usize,
+) -> bool {
+    let limit = limit.map_or(usize::MAX, |limit| limit.max(1) as usize);
+    let mut evaluated = 0;
+    let mut page_bytes = 0;
+    for (_, item) in matches.iter() {
+        let size = item_size(item);
+        if evaluated == limit || (evaluated > 0 && page_bytes + size > max_page_bytes) {
+            break;
+        }
+        page_bytes += size;
+        evaluated += 1;
+    }
+    let has_more = matches.len() > evaluated;
+    matches.truncate(evaluated);
+    has_more
+}
+
+fn key_of<'a>(item: &Item, attributes: impl Iterator<Item = &'a String>) -> Item {
+    attributes
+        .filter_map(|name| Some((name.clone(), item.get(name)?.clone())))
+        .collect()
+}
+
+// The filter applies after the page is read, as it does in DynamoDB
+fn filter_page(matches: Vec<(&String, &Item)>, filter: Option<&ConditionExpression>) -> Vec<Item> {
+    matches
+        .into_iter()
+        .filter(|(_, item)| filter.is_none_or(|f| f.evaluate(Some(item))))
+        .map(|(_, item)| item.clone())
+        .collect()
+}
+
+// Approximates DynamoDB's item size: attribute names plus their values
+fn item_size(item: &Item) -> usize {
+    item.iter()
+        .map(|(name, value)| name.len() + value_size(value))
+        .sum()
+}
+
+fn value_size(value: &AttributeValue) -> usize {
+    match value {
+        AttributeValue::S(s) | AttributeValue::N(s) => s.len(),
+        AttributeValue::B(b) => b.as_ref().len(),
+        AttributeValue::Ss(set) | AttributeValue::Ns(set) => set.iter().map(String::len).sum(),
+        AttributeValue::Bs(set) => set.iter().map(|b| b.as_ref().len()).sum(),
+        AttributeValue::L(list) => 3 + list.iter().map(|v| 1 + value_size(v)).sum::<usize>(),
+        AttributeValue::M(map) => 3 + item_size(map) + map.len(),
+        _ => 1,
+    }
+}
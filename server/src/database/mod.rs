@@ -1,5 +1,18 @@
-mod attribute_value_parser;
+mod attribute_value_json;
+pub mod attribute_value_parser;
+#[cfg(test)]
+mod contract_tests;
+pub mod db_cache;
 pub mod db_cloud;
 pub mod db_local;
+pub mod db_retry;
+pub mod db_sqlite;
 pub mod db_trait;
+pub mod dynamo_record;
+mod expression;
+mod local_journal;
+mod local_query;
+pub mod rate_limit_table;
+pub mod record_migration;
+pub mod table_schema;
 pub mod websocket_table;
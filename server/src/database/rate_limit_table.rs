@@ -0,0 +1,122 @@
+#![allow(dead_code)]
+use super::{
+    attribute_value_parser::{format_datetime, parse_attribute},
+    db_trait::IDatabase,
+    record_migration::{reformat_datetime, MigrationRegistry, SCHEMA_VERSION_ATTRIBUTE},
+    table_schema::{KeySchema, TableSchema},
+};
+use crate::domain::{
+    errors::LogicError,
+    rate_limit::{RateLimit, TokenBucket, MAX_EMPTY_BUCKET_LIFETIME},
+};
+use aws_sdk_dynamodb::types::{AttributeValue, Get, Put, TransactGetItem, TransactWriteItem};
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, env, sync::Arc};
+
+pub struct RateLimitTable {}
+
+impl RateLimitTable {
+    pub async fn from_db(
+        key: &str,
+        database: &Arc<dyn IDatabase>,
+    ) -> Result<Option<TokenBucket>, LogicError> {
+        let transaction = Self::get(key)?;
+        let output = database.read_single(transaction).await?;
+        match output.item {
+            Some(attribute) => Ok(Some(Self::from_map(&attribute)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Saves the buckets in one transaction
+    pub async fn to_db<'a>(
+        buckets: impl IntoIterator<Item = (&'a str, &'a (TokenBucket, RateLimit))>,
+        database: &Arc<dyn IDatabase>,
+    ) -> Result<(), LogicError> {
+        let transaction = buckets
+            .into_iter()
+            .map(|(key, (bucket, limit))| Self::save(key, bucket, limit))
+            .collect::<Result<Vec<_>, _>>()?;
+        database.write(transaction).await
+    }
+
+    fn from_map(hash_map: &HashMap<String, AttributeValue>) -> Result<TokenBucket, LogicError> {
+        let mut item = hash_map.clone();
+        Self::migrations().upcast(&mut item)?;
+        let tokens = parse_attribute(&item, "tokens")?;
+        let updated_at = parse_attribute(&item, "updated_at")?;
+        Ok(TokenBucket { tokens, updated_at })
+    }
+
+    pub fn migrations() -> MigrationRegistry {
+        MigrationRegistry::new()
+            .then(updated_at_to_rfc3339)
+            .then(add_ttl)
+    }
+
+    pub fn schema() -> TableSchema {
+        TableSchema::new(&Self::get_table_name(), KeySchema::hash("id")).with_ttl("ttl")
+    }
+
+    // Locally the websocket table is named "", so buckets need a name of their own
+    pub fn get_table_name() -> String {
+        env::var("RATE_LIMIT_TABLE_NAME").unwrap_or_else(|_| "RateLimit".to_string())
+    }
+
+    fn get(key: &str) -> Result<TransactGetItem, LogicError> {
+        let get_item = Get::builder()
+            .table_name(Self::get_table_name())
+            .key("id", AttributeValue::S(key.to_string()))
+            .build()
+            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        let transaction_item = TransactGetItem::builder().get(get_item).build();
+        Ok(transaction_item)
+    }
+
+    // The bucket expires once it has refilled, since a missing bucket is full
+    fn save(
+        key: &str,
+        bucket: &TokenBucket,
+        limit: &RateLimit,
+    ) -> Result<TransactWriteItem, LogicError> {
+        let put_item = Put::builder()
+            .table_name(Self::get_table_name())
+            .item("id", AttributeValue::S(key.to_string()))
+            .item("tokens", AttributeValue::N(bucket.tokens.to_string()))
+            .item(
+                "updated_at",
+                AttributeValue::S(format_datetime(&bucket.updated_at)),
+            )
+            .item("ttl", ttl_attribute(bucket.full_at(limit)))
+            .item(
+                SCHEMA_VERSION_ATTRIBUTE,
+                AttributeValue::N(Self::migrations().current_version().to_string()),
+            )
+            .build()
+            .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+        let transaction_item = TransactWriteItem::builder().put(put_item).build();
+        Ok(transaction_item)
+    }
+}
+
+// Version 1: timestamps are RFC 3339
+fn updated_at_to_rfc3339(item: &mut HashMap<String, AttributeValue>) -> Result<(), LogicError> {
+    reformat_datetime(item, "updated_at")
+}
+
+// Version 2: buckets expire through the table's TTL. The limit of an older
+// bucket is unknown, so it is kept for as long as an empty one could be.
+fn add_ttl(item: &mut HashMap<String, AttributeValue>) -> Result<(), LogicError> {
+    if !item.contains_key("ttl") {
+        let updated_at: DateTime<Utc> = parse_attribute(item, "updated_at")?;
+        let ttl = ttl_attribute(updated_at + MAX_EMPTY_BUCKET_LIFETIME);
+        item.insert("ttl".to_string(), ttl);
+    }
+    Ok(())
+}
+
+// In epoch seconds, rounded up so the bucket is never deleted early
+fn ttl_attribute(expires_at: DateTime<Utc>) -> AttributeValue {
+    let seconds = expires_at.timestamp() + i64::from(expires_at.timestamp_subsec_nanos() > 0);
+    AttributeValue::N(seconds.to_string())
+}
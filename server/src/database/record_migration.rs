@@ -0,0 +1,336 @@
+#![allow(dead_code)]
+use super::attribute_value_parser::{format_datetime, parse_attribute};
+use super::db_trait::IDatabase;
+use super::expression::Item;
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::operation::scan::ScanInput;
+use aws_sdk_dynamodb::types::{AttributeValue, Put, TransactWriteItem};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+pub const SCHEMA_VERSION_ATTRIBUTE: &str = "schema_version";
+
+// Rewrites an item from the schema version before it to the next one
+pub type Upcaster = fn(&mut Item) -> Result<(), LogicError>;
+
+// The upcasters of one table, in order. Items without a schema_version are
+// version 0, and the first upcaster takes them to version 1.
+#[derive(Default)]
+pub struct MigrationRegistry {
+    upcasters: Vec<Upcaster>,
+}
+
+impl MigrationRegistry {
+    pub fn new() -> Self {
+        MigrationRegistry { upcasters: vec![] }
+    }
+
+    pub fn then(mut self, upcaster: Upcaster) -> Self {
+        self.upcasters.push(upcaster);
+        self
+    }
+
+    pub fn current_version(&self) -> i64 {
+        self.upcasters.len() as i64
+    }
+
+    // Brings the item up to the current version, returning whether it
+    // changed. Items written by a newer deploy are left as they are.
+    pub fn upcast(&self, item: &mut Item) -> Result<bool, LogicError> {
+        let version = schema_version(item)?;
+        if version >= self.current_version() {
+            return Ok(false);
+        }
+        for upcaster in &self.upcasters[version as usize..] {
+            upcaster(item)?;
+        }
+        let current = AttributeValue::N(self.current_version().to_string());
+        item.insert(SCHEMA_VERSION_ATTRIBUTE.to_string(), current);
+        Ok(true)
+    }
+}
+
+// For upcasters: rewrites a timestamp in the current format, whichever
+// format it was written in
+pub fn reformat_datetime(item: &mut Item, name: &str) -> Result<(), LogicError> {
+    if item.contains_key(name) {
+        let datetime: DateTime<Utc> = parse_attribute(item, name)?;
+        let value = AttributeValue::S(format_datetime(&datetime));
+        item.insert(name.to_string(), value);
+    }
+    Ok(())
+}
+
+fn schema_version(item: &Item) -> Result<i64, LogicError> {
+    match item.get(SCHEMA_VERSION_ATTRIBUTE) {
+        None => Ok(0),
+        Some(AttributeValue::N(version)) => version
+            .parse()
+            .map_err(|_| LogicError::DatabaseError(format!("Invalid schema version {}", version))),
+        Some(_) => Err(LogicError::DatabaseError(
+            "The schema version is not a number".to_string(),
+        )),
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct MigrationSummary {
+    pub scanned: usize,
+    pub migrated: usize,
+    // Items that were rewritten by someone else while being migrated
+    pub skipped: usize,
+}
+
+// Scans the whole table and writes back every item that is behind the current
+// version. Each write is conditional on the item not having changed version,
+// so it never overwrites a record saved by a live connection.
+pub async fn migrate_table(
+    db: &Arc<dyn IDatabase>,
+    table_name: &str,
+    registry: &MigrationRegistry,
+) -> Result<MigrationSummary, LogicError> {
+    let mut summary = MigrationSummary::default();
+    let mut start_key = None;
+    loop {
+        let scan = ScanInput::builder()
+            .table_name(table_name)
+            .set_exclusive_start_key(start_key);
+        let page = db.scan(scan).await?;
+        for mut item in page.items {
+            summary.scanned += 1;
+            let version = schema_version(&item)?;
+            if !registry.upcast(&mut item)? {
+                continue;
+            }
+            match db
+                .write_single(conditional_put(table_name, item, version)?)
+                .await
+            {
+                Ok(()) => summary.migrated += 1,
+                Err(LogicError::ConditionalCheckFailed(_)) => summary.skipped += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        match page.last_evaluated_key {
+            Some(key) => start_key = Some(key),
+            None => return Ok(summary),
+        }
+    }
+}
+
+fn conditional_put(
+    table_name: &str,
+    item: Item,
+    version: i64,
+) -> Result<TransactWriteItem, LogicError> {
+    let put = Put::builder()
+        .table_name(table_name)
+        .set_item(Some(item))
+        .expression_attribute_names("#schema_version", SCHEMA_VERSION_ATTRIBUTE);
+    let put = if version == 0 {
+        put.condition_expression("attribute_not_exists(#schema_version)")
+    } else {
+        put.condition_expression("#schema_version = :version")
+            .expression_attribute_values(":version", AttributeValue::N(version.to_string()))
+    };
+    let put = put
+        .build()
+        .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
+    Ok(TransactWriteItem::builder().put(put).build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::attribute_value_parser::LEGACY_DATETIME_FORMAT;
+    use crate::database::db_local::DatabaseLocal;
+    use crate::database::websocket_table::WebsocketTable;
+    use aws_sdk_dynamodb::types::{Get, TransactGetItem};
+    use chrono::NaiveDateTime;
+    use std::collections::HashMap;
+
+    fn rename_nick(item: &mut Item) -> Result<(), LogicError> {
+        if let Some(nick) = item.remove("nick") {
+            item.insert("name".to_string(), nick);
+        }
+        Ok(())
+    }
+
+    fn add_room(item: &mut Item) -> Result<(), LogicError> {
+        item.entry("room_id".to_string())
+            .or_insert_with(|| AttributeValue::S("lobby".to_string()));
+        Ok(())
+    }
+
+    fn legacy(id: &str) -> Item {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S(id.to_string())),
+            ("nick".to_string(), AttributeValue::S("bob".to_string())),
+        ])
+    }
+
+    fn put_item(table_name: &str, item: Item) -> TransactWriteItem {
+        let put = Put::builder()
+            .table_name(table_name)
+            .set_item(Some(item))
+            .build()
+            .unwrap();
+        TransactWriteItem::builder().put(put).build()
+    }
+
+    #[test]
+    fn test_upcasts_from_stored_version() -> Result<(), LogicError> {
+        let registry = MigrationRegistry::new().then(rename_nick).then(add_room);
+        let mut item = legacy("id1");
+        assert!(registry.upcast(&mut item)?);
+        assert_eq!(item["name"], AttributeValue::S("bob".to_string()));
+        assert_eq!(item["room_id"], AttributeValue::S("lobby".to_string()));
+        assert_eq!(
+            item[SCHEMA_VERSION_ATTRIBUTE],
+            AttributeValue::N("2".to_string())
+        );
+
+        // Only the upcasters after the stored version apply
+        let mut item = legacy("id2");
+        item.insert(
+            SCHEMA_VERSION_ATTRIBUTE.to_string(),
+            AttributeValue::N("1".to_string()),
+        );
+        assert!(registry.upcast(&mut item)?);
+        assert!(item.contains_key("nick"));
+
+        // Items from a newer deploy are left alone
+        let mut item = legacy("id3");
+        item.insert(
+            SCHEMA_VERSION_ATTRIBUTE.to_string(),
+            AttributeValue::N("3".to_string()),
+        );
+        assert!(!registry.upcast(&mut item)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrates_every_page() -> Result<(), LogicError> {
+        let db = DatabaseLocal::new().await.with_max_page_bytes(100);
+        let db: Arc<dyn IDatabase> = Arc::new(db);
+        let registry = MigrationRegistry::new().then(rename_nick);
+        let writes = (0..10)
+            .map(|i| put_item("table", legacy(&format!("id{}", i))))
+            .collect();
+        db.write(writes).await?;
+
+        let summary = migrate_table(&db, "table", &registry).await?;
+        assert_eq!(summary.scanned, 10);
+        assert_eq!(summary.migrated, 10);
+        let get = Get::builder()
+            .table_name("table")
+            .key("id", AttributeValue::S("id7".to_string()))
+            .build()
+            .unwrap();
+        let item = db
+            .read_single(TransactGetItem::builder().get(get).build())
+            .await?
+            .item
+            .unwrap();
+        assert_eq!(item["name"], AttributeValue::S("bob".to_string()));
+
+        let summary = migrate_table(&db, "table", &registry).await?;
+        assert_eq!(summary.migrated, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reads_records_missing_required_fields() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        // As written before this series: no user_id, name, modified_at or ttl
+        let item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("id1".to_string())),
+            ("room_id".to_string(), AttributeValue::S("room".to_string())),
+        ]);
+        db.write_single(put_item(&WebsocketTable::get_table_name(), item))
+            .await?;
+        let mut record = WebsocketTable::from_db("id1", &db).await?;
+        assert_eq!(record.name, "Anonymous");
+        assert_eq!(record.user_id, "id1");
+        // Saving writes the current schema version
+        WebsocketTable::to_db(&mut record, &db).await?;
+        let summary = migrate_table(
+            &db,
+            &WebsocketTable::get_table_name(),
+            &WebsocketTable::migrations(),
+        )
+        .await?;
+        assert_eq!(
+            summary,
+            MigrationSummary {
+                scanned: 1,
+                migrated: 0,
+                skipped: 0
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stale_records_without_ttl_expire() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let table_name = WebsocketTable::get_table_name();
+        let item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("id1".to_string())),
+            ("room_id".to_string(), AttributeValue::S("room".to_string())),
+            (
+                "modified_at".to_string(),
+                AttributeValue::S("2024-01-01 12:00:00.000000".to_string()),
+            ),
+        ]);
+        db.write_single(put_item(&table_name, item)).await?;
+        assert!(WebsocketTable::from_db("id1", &db).await.is_err());
+        assert!(WebsocketTable::get_room_connections("room", &db)
+            .await?
+            .is_empty());
+        // Backfilling the ttl lets DynamoDB delete the record
+        migrate_table(&db, &table_name, &WebsocketTable::migrations()).await?;
+        let page = db
+            .scan(ScanInput::builder().table_name(&table_name))
+            .await?;
+        assert!(page.items.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rewrites_legacy_timestamps() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let table_name = WebsocketTable::get_table_name();
+        // Recent enough that the ttl it is given has not passed
+        let modified_at = Utc::now().format(LEGACY_DATETIME_FORMAT).to_string();
+        let mut legacy = legacy("id1");
+        legacy.insert(
+            "modified_at".to_string(),
+            AttributeValue::S(modified_at.clone()),
+        );
+        legacy.insert(
+            SCHEMA_VERSION_ATTRIBUTE.to_string(),
+            AttributeValue::N("1".to_string()),
+        );
+        db.write_single(put_item(&table_name, legacy)).await?;
+        let summary = migrate_table(&db, &table_name, &WebsocketTable::migrations()).await?;
+        assert_eq!(summary.migrated, 1);
+        let page = db
+            .scan(ScanInput::builder().table_name(&table_name))
+            .await?;
+        let item = &page.items[0];
+        let expected = NaiveDateTime::parse_from_str(&modified_at, LEGACY_DATETIME_FORMAT)
+            .unwrap()
+            .and_utc();
+        assert_eq!(
+            item["modified_at"],
+            AttributeValue::S(format_datetime(&expected))
+        );
+        let current = WebsocketTable::migrations().current_version();
+        assert_eq!(
+            item[SCHEMA_VERSION_ATTRIBUTE],
+            AttributeValue::N(current.to_string())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+#![allow(dead_code)]
+use crate::domain::errors::LogicError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeySchema {
+    pub hash_key: String,
+    pub range_key: Option<String>,
+}
+
+impl KeySchema {
+    pub fn hash(hash_key: &str) -> Self {
+        KeySchema {
+            hash_key: hash_key.to_string(),
+            range_key: None,
+        }
+    }
+
+    pub fn composite(hash_key: &str, range_key: &str) -> Self {
+        KeySchema {
+            hash_key: hash_key.to_string(),
+            range_key: Some(range_key.to_string()),
+        }
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.hash_key).chain(&self.range_key)
+    }
+
+    // Identifies the item within its table. Composite keys are joined with a
+    // unit separator, which does not occur in the ids used by the tables.
+    pub fn storage_key(
+        &self,
+        item: &HashMap<String, AttributeValue>,
+    ) -> Result<String, LogicError> {
+        self.attributes()
+            .map(|name| match item.get(name) {
+                Some(AttributeValue::S(value)) | Some(AttributeValue::N(value)) => {
+                    Ok(value.clone())
+                }
+                _ => Err(LogicError::DatabaseError(format!(
+                    "Missing key attribute {}",
+                    name
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|parts| parts.join("\u{1f}"))
+    }
+}
+
+// The keys and secondary indexes of a table, as declared in
+// terraform/dynamodb.tf, for databases that emulate DynamoDB locally
+#[derive(Clone, Debug)]
+pub struct TableSchema {
+    pub table_name: String,
+    pub primary_key: KeySchema,
+    pub indexes: HashMap<String, KeySchema>,
+    // The number attribute holding an expiry time in epoch seconds
+    pub ttl_attribute: Option<String>,
+}
+
+impl TableSchema {
+    pub fn new(table_name: &str, primary_key: KeySchema) -> Self {
+        TableSchema {
+            table_name: table_name.to_string(),
+            primary_key,
+            indexes: HashMap::new(),
+            ttl_attribute: None,
+        }
+    }
+
+    pub fn with_ttl(mut self, attribute: &str) -> Self {
+        self.ttl_attribute = Some(attribute.to_string());
+        self
+    }
+
+    // DynamoDB ignores a TTL attribute that is missing or not a number
+    pub fn is_expired(&self, item: &HashMap<String, AttributeValue>, now: i64) -> bool {
+        let Some(attribute) = &self.ttl_attribute else {
+            return false;
+        };
+        match item.get(attribute) {
+            Some(AttributeValue::N(ttl)) => ttl.parse::<f64>().is_ok_and(|ttl| ttl <= now as f64),
+            _ => false,
+        }
+    }
+
+    pub fn with_index(mut self, index_name: &str, key: KeySchema) -> Self {
+        self.indexes.insert(index_name.to_string(), key);
+        self
+    }
+
+    pub fn key_schema(&self, index_name: Option<&str>) -> Result<&KeySchema, LogicError> {
+        match index_name {
+            None => Ok(&self.primary_key),
+            Some(index_name) => self
+                .indexes
+                .get(index_name)
+                .ok_or(LogicError::DatabaseError(format!(
+                    "The table {} does not have the index {}",
+                    self.table_name, index_name
+                ))),
+        }
+    }
+}
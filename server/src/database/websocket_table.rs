@@ -1,12 +1,17 @@
 #![allow(dead_code)]
 use super::{
-    attribute_value_parser::{parse_attribute_value, DATETIME_FORMAT},
+    attribute_value_parser::{format_datetime, parse_attribute},
     db_trait::IDatabase,
+    dynamo_record::DynamoRecord,
+    record_migration::{reformat_datetime, MigrationRegistry, SCHEMA_VERSION_ATTRIBUTE},
+    table_schema::{KeySchema, TableSchema},
+};
+use crate::domain::{
+    connection_ttl::ConnectionTtl, errors::LogicError, websocket_record::WebsocketRecord,
 };
-use crate::domain::{errors::LogicError, websocket_record::WebsocketRecord};
 use aws_sdk_dynamodb::operation::query::QueryInput;
 use aws_sdk_dynamodb::types::{AttributeValue, Get, Put, TransactGetItem, TransactWriteItem};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use std::{collections::HashMap, env, sync::Arc};
 
 pub struct WebsocketTable {}
@@ -22,15 +27,21 @@ impl WebsocketTable {
             .item
             .ok_or(LogicError::DatabaseError("Item not found".to_string()))?;
         let item = Self::from_map(&attribute)?;
+        // DynamoDB can take days to delete an expired item
+        if item.is_expired(Utc::now()) {
+            return Err(LogicError::DatabaseError("Item not found".to_string()));
+        }
         Ok(item)
     }
 
     pub async fn to_db(
-        record: &WebsocketRecord,
+        record: &mut WebsocketRecord,
         db: &Arc<dyn IDatabase>,
     ) -> Result<(), LogicError> {
         let transaction = Self::save(record)?;
-        db.write_single(transaction).await
+        db.write_single(transaction).await?;
+        record.version += 1;
+        Ok(())
     }
 
     pub async fn get_room_connections(
@@ -41,31 +52,61 @@ impl WebsocketTable {
             .table_name(Self::get_table_name())
             .index_name("room_id_index")
             .key_condition_expression("room_id = :room_id")
-            .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()));
-        let output = db.query(query).await?;
+            // TTL is a reserved word, and DynamoDB can take days to delete
+            // an expired item. Records without a ttl are left out until
+            // migrate_records has given them one.
+            .filter_expression("#ttl > :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":room_id", AttributeValue::S(room_id.to_string()))
+            .expression_attribute_values(
+                ":now",
+                AttributeValue::N(Utc::now().timestamp().to_string()),
+            );
         let mut items = vec![];
-        for item in output {
-            let item = Self::from_map(&item)?;
-            items.push(item);
+        let mut start_key = None;
+        // A query returns at most 1MB, so large rooms span several pages
+        loop {
+            let page = db
+                .query(query.clone().set_exclusive_start_key(start_key))
+                .await?;
+            for item in page.items {
+                let item = Self::from_map(&item)?;
+                // The page may come from a cache, filtered a little earlier
+                if !item.is_expired(Utc::now()) {
+                    items.push(item);
+                }
+            }
+            match page.last_evaluated_key {
+                Some(key) => start_key = Some(key),
+                None => return Ok(items),
+            }
         }
-        Ok(items)
     }
 
     fn from_map(hash_map: &HashMap<String, AttributeValue>) -> Result<WebsocketRecord, LogicError> {
-        let id = parse_attribute_value::<String>(hash_map.get("id"))?;
-        let room_id = parse_attribute_value::<String>(hash_map.get("room_id"))?;
-        let name = parse_attribute_value::<String>(hash_map.get("name"))?;
-        let modified_at = parse_attribute_value::<DateTime<Utc>>(hash_map.get("modified_at"))?;
-        let item = WebsocketRecord {
-            id,
-            room_id,
-            name,
-            modified_at,
-        };
-        Ok(item)
+        let mut item = hash_map.clone();
+        Self::migrations().upcast(&mut item)?;
+        WebsocketRecord::from_item(&item)
+    }
+
+    // Applied to every record that is read, so records written by an older
+    // deploy stay readable. Add an upcaster here whenever a required field is
+    // added, and run migrate_records to rewrite stored records in bulk.
+    pub fn migrations() -> MigrationRegistry {
+        MigrationRegistry::new()
+            .then(add_name_and_modified_at)
+            .then(modified_at_to_rfc3339)
+            .then(add_user_id)
+            .then(add_ttl)
+    }
+
+    pub fn schema() -> TableSchema {
+        TableSchema::new(&Self::get_table_name(), KeySchema::hash("id"))
+            .with_index("room_id_index", KeySchema::hash("room_id"))
+            .with_ttl("ttl")
     }
 
-    fn get_table_name() -> String {
+    pub fn get_table_name() -> String {
         env::var("WEBSOCKET_TABLE_NAME").unwrap_or_else(|_| "".to_string())
     }
 
@@ -79,18 +120,27 @@ impl WebsocketTable {
         Ok(transaction_item)
     }
 
+    // Writes the next version, on condition that the stored one is still the
+    // version that was read
     pub fn save(record: &WebsocketRecord) -> Result<TransactWriteItem, LogicError> {
-        let put_item = Put::builder()
+        let mut item = record.to_item();
+        let next_version = AttributeValue::N((record.version + 1).to_string());
+        item.insert("version".to_string(), next_version);
+        let schema_version = AttributeValue::N(Self::migrations().current_version().to_string());
+        item.insert(SCHEMA_VERSION_ATTRIBUTE.to_string(), schema_version);
+        let put = Put::builder()
             .table_name(Self::get_table_name())
-            .item("id", AttributeValue::S(record.id.to_string()))
-            .item(
-                "modified_at",
-                AttributeValue::S(record.modified_at.format(DATETIME_FORMAT).to_string()),
-            )
-            .item("room_id", AttributeValue::S(record.room_id.to_string()))
-            .item("name", AttributeValue::S(record.name.to_string()));
-
-        let put_item = put_item
+            .set_item(Some(item));
+        let put = if record.version == 0 {
+            put.condition_expression("attribute_not_exists(version)")
+        } else {
+            put.condition_expression("version = :version")
+                .expression_attribute_values(
+                    ":version",
+                    AttributeValue::N(record.version.to_string()),
+                )
+        };
+        let put_item = put
             .build()
             .map_err(|e| LogicError::DatabaseError(e.to_string()))?;
         let transaction_item = TransactWriteItem::builder().put(put_item).build();
@@ -107,3 +157,41 @@ impl WebsocketTable {
         Ok(transaction_item)
     }
 }
+
+// Version 1: name and modified_at became required
+fn add_name_and_modified_at(item: &mut HashMap<String, AttributeValue>) -> Result<(), LogicError> {
+    item.entry("name".to_string())
+        .or_insert_with(|| AttributeValue::S("Anonymous".to_string()));
+    item.entry("modified_at".to_string())
+        .or_insert_with(|| AttributeValue::S(format_datetime(&Utc::now())));
+    Ok(())
+}
+
+// Version 2: timestamps are RFC 3339
+fn modified_at_to_rfc3339(item: &mut HashMap<String, AttributeValue>) -> Result<(), LogicError> {
+    reformat_datetime(item, "modified_at")
+}
+
+// Version 3: user_id became required. Older connections were never
+// authenticated, so each is treated as its own user.
+fn add_user_id(item: &mut HashMap<String, AttributeValue>) -> Result<(), LogicError> {
+    if !item.contains_key("user_id") {
+        let id = item
+            .get("id")
+            .cloned()
+            .ok_or(LogicError::DatabaseError("Record has no id".to_string()))?;
+        item.insert("user_id".to_string(), id);
+    }
+    Ok(())
+}
+
+// Version 4: ttl became required. Older records expire as if they had not
+// been active since they were last modified.
+fn add_ttl(item: &mut HashMap<String, AttributeValue>) -> Result<(), LogicError> {
+    if !item.contains_key("ttl") {
+        let modified_at = parse_attribute(item, "modified_at")?;
+        let ttl = ConnectionTtl::from_env().expires_at(modified_at);
+        item.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
+    }
+    Ok(())
+}
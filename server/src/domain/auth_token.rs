@@ -0,0 +1,82 @@
+#![allow(dead_code)]
+use super::errors::LogicError;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const LOCAL_SECRET: &str = "local-secret";
+
+// Tokens take the form "<user_id>.<hex hmac-sha256 of user_id>", signed with a
+// secret shared between the local server and the cloud authorizer.
+pub struct TokenValidator {
+    secret: Vec<u8>,
+}
+
+impl TokenValidator {
+    pub fn new(secret: &str) -> Self {
+        TokenValidator {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let secret = env::var("AUTH_SECRET").unwrap_or_else(|_| LOCAL_SECRET.to_string());
+        Self::new(&secret)
+    }
+
+    pub fn issue(&self, user_id: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(user_id.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        format!("{}.{}", user_id, signature)
+    }
+
+    pub fn validate(&self, token: &str) -> Result<String, LogicError> {
+        let (user_id, signature) = token
+            .rsplit_once('.')
+            .ok_or(LogicError::Unauthorized("malformed token".to_string()))?;
+        if user_id.is_empty() {
+            return Err(LogicError::Unauthorized("malformed token".to_string()));
+        }
+        let signature = hex::decode(signature)
+            .map_err(|_| LogicError::Unauthorized("malformed token".to_string()))?;
+        let mut mac = self.mac();
+        mac.update(user_id.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| LogicError::Unauthorized("invalid signature".to_string()))?;
+        Ok(user_id.to_string())
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issued_token_is_valid() {
+        let validator = TokenValidator::new("secret");
+        let token = validator.issue("user1");
+        assert_eq!(validator.validate(&token), Ok("user1".to_string()));
+    }
+
+    #[test]
+    fn test_rejects_token_from_other_secret() {
+        let token = TokenValidator::new("other").issue("user1");
+        let result = TokenValidator::new("secret").validate(&token);
+        assert!(matches!(result, Err(LogicError::Unauthorized(_))));
+    }
+
+    #[test]
+    fn test_rejects_malformed_token() {
+        let validator = TokenValidator::new("secret");
+        assert!(validator.validate("user1").is_err());
+        assert!(validator.validate("user1.nothex").is_err());
+        assert!(validator.validate(".abcd").is_err());
+    }
+}
@@ -0,0 +1,41 @@
+#![allow(dead_code)]
+use chrono::{DateTime, Utc};
+use std::env;
+
+// API Gateway closes connections that are idle for 10 minutes, so a record
+// that has not been refreshed for longer belongs to a dead connection
+const DEFAULT_TTL_SECONDS: i64 = 15 * 60;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionTtl {
+    pub ttl_seconds: i64,
+}
+
+impl Default for ConnectionTtl {
+    fn default() -> Self {
+        ConnectionTtl {
+            ttl_seconds: DEFAULT_TTL_SECONDS,
+        }
+    }
+}
+
+impl ConnectionTtl {
+    pub fn from_env() -> Self {
+        let ttl_seconds = env::var("CONNECTION_TTL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECONDS);
+        ConnectionTtl { ttl_seconds }
+    }
+
+    // In epoch seconds, the format DynamoDB TTL expects
+    pub fn expires_at(&self, now: DateTime<Utc>) -> i64 {
+        now.timestamp() + self.ttl_seconds
+    }
+
+    // Activity only rewrites the record once half of its TTL has passed, so
+    // that every chat message does not cost a write
+    pub fn needs_refresh(&self, ttl: i64, now: DateTime<Utc>) -> bool {
+        ttl - now.timestamp() < self.ttl_seconds / 2
+    }
+}
@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+use serde::{Deserialize, Serialize};
+
+pub const RATE_LIMITED: &str = "rate_limited";
+pub const MESSAGE_REJECTED: &str = "message_rejected";
+pub const MESSAGE_TOO_LARGE: &str = "message_too_large";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ErrorFrame {
+    pub error: String,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl ErrorFrame {
+    pub fn rate_limited(retry_after_ms: u64) -> Self {
+        ErrorFrame {
+            error: RATE_LIMITED.to_string(),
+            detail: "Too many requests".to_string(),
+            retry_after_ms: Some(retry_after_ms),
+        }
+    }
+
+    pub fn message_rejected(reason: &str) -> Self {
+        ErrorFrame {
+            error: MESSAGE_REJECTED.to_string(),
+            detail: reason.to_string(),
+            retry_after_ms: None,
+        }
+    }
+
+    pub fn message_too_large(detail: &str) -> Self {
+        ErrorFrame {
+            error: MESSAGE_TOO_LARGE.to_string(),
+            detail: detail.to_string(),
+            retry_after_ms: None,
+        }
+    }
+}
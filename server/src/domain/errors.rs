@@ -7,8 +7,22 @@ use std::fmt;
 #[derive(Debug, PartialEq)]
 pub enum LogicError {
     BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    HeaderTooLarge(String),
     WebsocketError(String),
+    // The client has disconnected, so the connection can be cleaned up
+    ConnectionGone(String),
     DatabaseError(String),
+    ConditionalCheckFailed(String),
+    // The database is over its capacity, so the request can be retried
+    Throttled(String),
+    // Another transaction was changing the same items
+    TransactionConflict(String),
+    // The request was malformed, which retrying will not fix
+    ValidationError(String),
+    // The table or index does not exist
+    ResourceNotFound(String),
     InternalError(String),
     SerializationError(String),
 }
@@ -19,12 +33,39 @@ impl fmt::Display for LogicError {
             LogicError::BadRequest(ref msg) => {
                 write!(f, "[BadRequest] {}", msg)
             }
+            LogicError::Unauthorized(ref msg) => {
+                write!(f, "[Unauthorized] {}", msg)
+            }
+            LogicError::Forbidden(ref msg) => {
+                write!(f, "[Forbidden] {}", msg)
+            }
+            LogicError::HeaderTooLarge(ref msg) => {
+                write!(f, "[HeaderTooLarge] {}", msg)
+            }
             LogicError::DatabaseError(ref msg) => {
                 write!(f, "[DatabaseError] {}", msg)
             }
+            LogicError::ConditionalCheckFailed(ref msg) => {
+                write!(f, "[ConditionalCheckFailed] {}", msg)
+            }
+            LogicError::Throttled(ref msg) => {
+                write!(f, "[Throttled] {}", msg)
+            }
+            LogicError::TransactionConflict(ref msg) => {
+                write!(f, "[TransactionConflict] {}", msg)
+            }
+            LogicError::ValidationError(ref msg) => {
+                write!(f, "[ValidationError] {}", msg)
+            }
+            LogicError::ResourceNotFound(ref msg) => {
+                write!(f, "[ResourceNotFound] {}", msg)
+            }
             LogicError::WebsocketError(ref msg) => {
                 write!(f, "[WebsocketError] {}", msg)
             }
+            LogicError::ConnectionGone(ref msg) => {
+                write!(f, "[ConnectionGone] {}", msg)
+            }
             LogicError::InternalError(ref msg) => {
                 write!(f, "[InternalError] {}", msg)
             }
@@ -39,8 +80,19 @@ impl Error for LogicError {}
 
 impl IntoResponse for LogicError {
     fn into_response(self) -> Response {
+        let status = match self {
+            LogicError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            LogicError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            LogicError::Forbidden(_) => StatusCode::FORBIDDEN,
+            LogicError::HeaderTooLarge(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            LogicError::ConditionalCheckFailed(_) => StatusCode::CONFLICT,
+            LogicError::TransactionConflict(_) => StatusCode::CONFLICT,
+            LogicError::Throttled(_) => StatusCode::SERVICE_UNAVAILABLE,
+            LogicError::ConnectionGone(_) => StatusCode::GONE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
         let body = self.to_string();
-        (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+        (status, body).into_response()
     }
 }
 
@@ -0,0 +1,42 @@
+#![allow(dead_code)]
+use std::env;
+use std::time::Duration;
+
+const DEFAULT_MAX_CONCURRENCY: usize = 32;
+const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanOutPolicy {
+    // How many recipients are notified at the same time
+    pub max_concurrency: usize,
+    // A recipient that takes longer is counted as failed, so that it cannot
+    // hold up the rest of the room
+    pub timeout: Duration,
+}
+
+impl Default for FanOutPolicy {
+    fn default() -> Self {
+        FanOutPolicy {
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+        }
+    }
+}
+
+impl FanOutPolicy {
+    pub fn from_env() -> Self {
+        let max_concurrency = env::var("FAN_OUT_CONCURRENCY")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENCY);
+        let timeout_ms = env::var("FAN_OUT_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_TIMEOUT_MS);
+        FanOutPolicy {
+            max_concurrency,
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+}
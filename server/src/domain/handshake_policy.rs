@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+use super::errors::LogicError;
+use axum::http::{header, HeaderMap};
+use std::env;
+
+const DEFAULT_ALLOWED_ORIGINS: &str = "http://localhost:4321";
+const DEFAULT_MAX_HEADER_BYTES: usize = 8192;
+const DEFAULT_PROTOCOL: &str = "chat";
+
+pub struct HandshakePolicy {
+    pub allowed_origins: Vec<String>,
+    pub max_header_bytes: usize,
+    pub required_protocol: String,
+}
+
+impl HandshakePolicy {
+    pub fn from_env() -> Self {
+        let allowed_origins = env::var("ALLOWED_ORIGINS")
+            .unwrap_or_else(|_| DEFAULT_ALLOWED_ORIGINS.to_string())
+            .split(',')
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        let max_header_bytes = env::var("MAX_HANDSHAKE_HEADER_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_HEADER_BYTES);
+        let required_protocol =
+            env::var("WEBSOCKET_PROTOCOL").unwrap_or_else(|_| DEFAULT_PROTOCOL.to_string());
+        HandshakePolicy {
+            allowed_origins,
+            max_header_bytes,
+            required_protocol,
+        }
+    }
+
+    pub fn validate(&self, headers: &HeaderMap) -> Result<(), LogicError> {
+        self.validate_header_size(headers)?;
+        self.validate_origin(headers)?;
+        self.validate_protocol(headers)?;
+        Ok(())
+    }
+
+    fn validate_header_size(&self, headers: &HeaderMap) -> Result<(), LogicError> {
+        let size: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if size > self.max_header_bytes {
+            return Err(LogicError::HeaderTooLarge(format!(
+                "handshake headers are {} bytes, limit is {}",
+                size, self.max_header_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_origin(&self, headers: &HeaderMap) -> Result<(), LogicError> {
+        // Browsers always send an Origin, so non-browser clients without one
+        // cannot be used for cross-site hijacking
+        let origin = match headers.get(header::ORIGIN) {
+            None => return Ok(()),
+            Some(origin) => origin
+                .to_str()
+                .map_err(|_| LogicError::Forbidden("invalid origin".to_string()))?,
+        };
+        if self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            Ok(())
+        } else {
+            Err(LogicError::Forbidden(format!(
+                "origin {} is not allowed",
+                origin
+            )))
+        }
+    }
+
+    // A client that offers subprotocols fails the handshake unless the
+    // response selects one of them
+    pub fn offers_protocol(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|protocol| protocol.trim() == self.required_protocol)
+    }
+
+    fn validate_protocol(&self, headers: &HeaderMap) -> Result<(), LogicError> {
+        if self.offers_protocol(headers) {
+            Ok(())
+        } else {
+            Err(LogicError::BadRequest(format!(
+                "subprotocol {} is required",
+                self.required_protocol
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn make_policy() -> HandshakePolicy {
+        HandshakePolicy {
+            allowed_origins: vec!["http://allowed.com".to_string()],
+            max_header_bytes: 256,
+            required_protocol: "chat".to_string(),
+        }
+    }
+
+    fn make_headers(origin: Option<&str>, protocol: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        }
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_str(protocol).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_accepts_allowed_origin() {
+        let headers = make_headers(Some("http://allowed.com"), "other, chat");
+        assert_eq!(make_policy().validate(&headers), Ok(()));
+    }
+
+    #[test]
+    fn test_accepts_missing_origin() {
+        let headers = make_headers(None, "chat");
+        assert_eq!(make_policy().validate(&headers), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_unknown_origin() {
+        let headers = make_headers(Some("http://evil.com"), "chat");
+        let result = make_policy().validate(&headers);
+        assert!(matches!(result, Err(LogicError::Forbidden(_))));
+    }
+
+    #[test]
+    fn test_rejects_missing_protocol() {
+        let headers = make_headers(Some("http://allowed.com"), "other");
+        let result = make_policy().validate(&headers);
+        assert!(matches!(result, Err(LogicError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_rejects_large_headers() {
+        let mut headers = make_headers(None, "chat");
+        let cookie = "a".repeat(512);
+        headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        let result = make_policy().validate(&headers);
+        assert!(matches!(result, Err(LogicError::HeaderTooLarge(_))));
+    }
+}
@@ -0,0 +1,39 @@
+#![allow(dead_code)]
+use std::env;
+
+const DEFAULT_MAX_TEXT_LENGTH: usize = 1000;
+const DEFAULT_MAX_FRAME_BYTES: usize = 2048;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageLimits {
+    // Counted in characters, and only applied to chat messages
+    pub max_text_length: usize,
+    // Counted in bytes, and applied to every frame a client sends
+    pub max_frame_bytes: usize,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        MessageLimits {
+            max_text_length: DEFAULT_MAX_TEXT_LENGTH,
+            max_frame_bytes: DEFAULT_MAX_FRAME_BYTES,
+        }
+    }
+}
+
+impl MessageLimits {
+    pub fn from_env() -> Self {
+        let max_text_length = env::var("MAX_TEXT_LENGTH")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_TEXT_LENGTH);
+        let max_frame_bytes = env::var("MAX_FRAME_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_FRAME_BYTES);
+        MessageLimits {
+            max_text_length,
+            max_frame_bytes,
+        }
+    }
+}
@@ -1,5 +1,13 @@
+pub mod auth_token;
+pub mod connection_ttl;
+pub mod error_frame;
 pub mod errors;
+pub mod fan_out_policy;
+pub mod handshake_policy;
 pub mod message;
+pub mod message_limits;
+pub mod rate_limit;
+pub mod server_event;
 pub mod tracing_utils;
 pub mod vec_utils;
 pub mod websocket_record;
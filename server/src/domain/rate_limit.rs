@@ -0,0 +1,305 @@
+#![allow(dead_code)]
+use super::errors::LogicError;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    Message,
+    Join,
+    Heartbeat,
+}
+
+impl CommandKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandKind::Message => "message",
+            CommandKind::Join => "join",
+            CommandKind::Heartbeat => "heartbeat",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RateLimit {
+    pub capacity: f64,
+    pub refill_per_second: f64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CommandRateLimits {
+    pub connection: Option<RateLimit>,
+    pub user: Option<RateLimit>,
+}
+
+// Expects JSON such as
+// {"message": {"connection": {"capacity": 10, "refill_per_second": 2}}}
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(flatten)]
+    pub commands: HashMap<CommandKind, CommandRateLimits>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let mut commands = HashMap::new();
+        commands.insert(
+            CommandKind::Message,
+            CommandRateLimits {
+                connection: Some(RateLimit {
+                    capacity: 10.0,
+                    refill_per_second: 2.0,
+                }),
+                user: Some(RateLimit {
+                    capacity: 20.0,
+                    refill_per_second: 4.0,
+                }),
+            },
+        );
+        commands.insert(
+            CommandKind::Join,
+            CommandRateLimits {
+                connection: Some(RateLimit {
+                    capacity: 5.0,
+                    refill_per_second: 0.2,
+                }),
+                user: Some(RateLimit {
+                    capacity: 10.0,
+                    refill_per_second: 0.5,
+                }),
+            },
+        );
+        // Clients only need a heartbeat every few minutes
+        commands.insert(
+            CommandKind::Heartbeat,
+            CommandRateLimits {
+                connection: Some(RateLimit {
+                    capacity: 3.0,
+                    refill_per_second: 0.05,
+                }),
+                user: Some(RateLimit {
+                    capacity: 20.0,
+                    refill_per_second: 0.5,
+                }),
+            },
+        );
+        RateLimitConfig { commands }
+    }
+}
+
+impl RateLimitConfig {
+    // A malformed RATE_LIMITS is logged rather than failing every request
+    pub fn from_env() -> Self {
+        let Ok(json) = env::var("RATE_LIMITS") else {
+            return Self::default();
+        };
+        Self::from_json(&json).unwrap_or_else(|error| {
+            tracing::error!(error = %error, "RATE_LIMITS is not valid, using the defaults");
+            Self::default()
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, LogicError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn for_command(&self, kind: CommandKind) -> CommandRateLimits {
+        self.commands.get(&kind).cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RateLimitOutcome {
+    Allowed,
+    Limited { retry_after_ms: u64 },
+}
+
+pub const MAX_EMPTY_BUCKET_LIFETIME: Duration = Duration::days(1);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TokenBucket {
+    pub fn full(limit: &RateLimit, now: DateTime<Utc>) -> Self {
+        TokenBucket {
+            tokens: limit.capacity,
+            updated_at: now,
+        }
+    }
+
+    // A full bucket behaves exactly like one that was never stored
+    pub fn is_full(&self, limit: &RateLimit, now: DateTime<Utc>) -> bool {
+        let elapsed_ms = (now - self.updated_at).num_milliseconds().max(0) as f64;
+        self.tokens + elapsed_ms / 1000.0 * limit.refill_per_second >= limit.capacity
+    }
+
+    // When the bucket will be full again, after which it need not be stored.
+    // A bucket that never refills is kept for MAX_EMPTY_BUCKET_LIFETIME.
+    pub fn full_at(&self, limit: &RateLimit) -> DateTime<Utc> {
+        let missing = (limit.capacity - self.tokens).max(0.0);
+        if missing == 0.0 {
+            return self.updated_at;
+        }
+        if limit.refill_per_second <= 0.0 {
+            return self.updated_at + MAX_EMPTY_BUCKET_LIFETIME;
+        }
+        let refill_ms = (missing / limit.refill_per_second * 1000.0).ceil() as i64;
+        self.updated_at + Duration::milliseconds(refill_ms).min(MAX_EMPTY_BUCKET_LIFETIME)
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: DateTime<Utc>) {
+        let elapsed_ms = (now - self.updated_at).num_milliseconds().max(0) as f64;
+        let refilled = self.tokens + elapsed_ms / 1000.0 * limit.refill_per_second;
+        self.tokens = refilled.min(limit.capacity);
+        self.updated_at = now;
+    }
+
+    // None when a token is available now
+    fn retry_after_ms(&self, limit: &RateLimit) -> Option<u64> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        let missing = 1.0 - self.tokens;
+        if limit.refill_per_second > 0.0 {
+            Some((missing / limit.refill_per_second * 1000.0).ceil() as u64)
+        } else {
+            Some(u64::MAX)
+        }
+    }
+}
+
+// Takes a token from every bucket, or from none of them if any is empty, so a
+// command rejected by one limit does not use up the others
+pub fn try_take_all(
+    buckets: &mut [(TokenBucket, RateLimit)],
+    now: DateTime<Utc>,
+) -> RateLimitOutcome {
+    let mut retry_after_ms = None;
+    for (bucket, limit) in buckets.iter_mut() {
+        bucket.refill(limit, now);
+        retry_after_ms = retry_after_ms.max(bucket.retry_after_ms(limit));
+    }
+    if let Some(retry_after_ms) = retry_after_ms {
+        return RateLimitOutcome::Limited { retry_after_ms };
+    }
+    for (bucket, _) in buckets.iter_mut() {
+        bucket.tokens -= 1.0;
+    }
+    RateLimitOutcome::Allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    const LIMIT: RateLimit = RateLimit {
+        capacity: 2.0,
+        refill_per_second: 1.0,
+    };
+
+    fn try_take(bucket: &mut TokenBucket, now: DateTime<Utc>) -> RateLimitOutcome {
+        let mut buckets = [(bucket.clone(), LIMIT)];
+        let outcome = try_take_all(&mut buckets, now);
+        *bucket = buckets[0].0.clone();
+        outcome
+    }
+
+    #[test]
+    fn test_bucket_limits_after_capacity() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket::full(&LIMIT, now);
+        assert_eq!(try_take(&mut bucket, now), RateLimitOutcome::Allowed);
+        assert_eq!(try_take(&mut bucket, now), RateLimitOutcome::Allowed);
+        assert_eq!(
+            try_take(&mut bucket, now),
+            RateLimitOutcome::Limited {
+                retry_after_ms: 1000
+            }
+        );
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+        let later = now + Duration::milliseconds(1500);
+        assert_eq!(try_take(&mut bucket, later), RateLimitOutcome::Allowed);
+        assert_eq!(
+            try_take(&mut bucket, later),
+            RateLimitOutcome::Limited {
+                retry_after_ms: 500
+            }
+        );
+    }
+
+    #[test]
+    fn test_bucket_is_full_once_refilled() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket::full(&LIMIT, now);
+        assert!(bucket.is_full(&LIMIT, now));
+        try_take(&mut bucket, now);
+        assert!(!bucket.is_full(&LIMIT, now));
+        assert!(bucket.is_full(&LIMIT, now + Duration::seconds(1)));
+    }
+
+    #[test]
+    fn test_bucket_full_at() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket::full(&LIMIT, now);
+        assert_eq!(bucket.full_at(&LIMIT), now);
+        try_take(&mut bucket, now);
+        try_take(&mut bucket, now);
+        assert_eq!(bucket.full_at(&LIMIT), now + Duration::seconds(2));
+        let never_refills = RateLimit {
+            capacity: 1.0,
+            refill_per_second: 0.0,
+        };
+        assert_eq!(
+            bucket.full_at(&never_refills),
+            now + MAX_EMPTY_BUCKET_LIFETIME
+        );
+    }
+
+    #[test]
+    fn test_empty_bucket_takes_from_none() {
+        let now = Utc::now();
+        let empty = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+        let mut buckets = [(TokenBucket::full(&LIMIT, now), LIMIT), (empty, LIMIT)];
+        assert_eq!(
+            try_take_all(&mut buckets, now),
+            RateLimitOutcome::Limited {
+                retry_after_ms: 1000
+            }
+        );
+        assert_eq!(buckets[0].0.tokens, LIMIT.capacity);
+    }
+
+    #[test]
+    fn test_config_parses_per_command() -> Result<(), LogicError> {
+        let json = r#"{"join": {"user": {"capacity": 1, "refill_per_second": 0.1}}}"#;
+        let config = RateLimitConfig::from_json(json)?;
+        let join = config.for_command(CommandKind::Join);
+        assert!(join.connection.is_none());
+        assert_eq!(join.user.unwrap().capacity, 1.0);
+        assert!(config.for_command(CommandKind::Message).user.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_config_is_an_error() {
+        assert!(RateLimitConfig::from_json(r#"{"message": 1}"#).is_err());
+    }
+}
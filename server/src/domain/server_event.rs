@@ -0,0 +1,140 @@
+#![allow(dead_code)]
+use super::{error_frame::ErrorFrame, errors::LogicError, message::Message};
+use serde::{Deserialize, Serialize};
+
+// Bumped when an existing event changes shape. Adding an event does not need
+// a new version, since clients ignore types they do not know.
+pub const SERVER_EVENT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Joined,
+    Left,
+}
+
+// Everything the server sends to a client. On the wire it is a JSON object
+// with the version, a `type` tag and the event's own fields, for example
+// {"version":1,"type":"message","author_name":"a","text":"hi","sent_at":"..."}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Message(Message),
+    Error(ErrorFrame),
+    Presence {
+        room_id: String,
+        name: String,
+        status: PresenceStatus,
+    },
+    // Confirms that a command was applied
+    Ack {
+        command: String,
+    },
+    Room {
+        room_id: String,
+        member_count: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u32,
+    #[serde(flatten)]
+    event: &'a ServerEvent,
+}
+
+#[derive(Deserialize)]
+struct OwnedEnvelope {
+    version: u32,
+    #[serde(flatten)]
+    event: ServerEvent,
+}
+
+impl ServerEvent {
+    // Both servers send exactly this, so their wire formats cannot drift apart
+    pub fn to_json(&self) -> Result<String, LogicError> {
+        let envelope = Envelope {
+            version: SERVER_EVENT_VERSION,
+            event: self,
+        };
+        Ok(serde_json::to_string(&envelope)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, LogicError> {
+        let envelope: OwnedEnvelope = serde_json::from_str(json)?;
+        if envelope.version != SERVER_EVENT_VERSION {
+            return Err(LogicError::SerializationError(format!(
+                "unsupported event version {}",
+                envelope.version
+            )));
+        }
+        Ok(envelope.event)
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            ServerEvent::Message(_) => "message",
+            ServerEvent::Error(_) => "error",
+            ServerEvent::Presence { .. } => "presence",
+            ServerEvent::Ack { .. } => "ack",
+            ServerEvent::Room { .. } => "room",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_message_wire_format() -> Result<(), LogicError> {
+        let sent_at = "2024-01-02T03:04:05Z".parse().unwrap();
+        let event = ServerEvent::Message(Message {
+            author_name: "name".to_string(),
+            text: "hello".to_string(),
+            sent_at,
+        });
+        let value: Value = serde_json::from_str(&event.to_json()?)?;
+        let expected = json!({
+            "version": 1,
+            "type": "message",
+            "author_name": "name",
+            "text": "hello",
+            "sent_at": "2024-01-02T03:04:05Z",
+        });
+        assert_eq!(value, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trips_every_type() -> Result<(), LogicError> {
+        let events = vec![
+            ServerEvent::Error(ErrorFrame::rate_limited(10)),
+            ServerEvent::Presence {
+                room_id: "room".to_string(),
+                name: "name".to_string(),
+                status: PresenceStatus::Joined,
+            },
+            ServerEvent::Ack {
+                command: "UserUpdate".to_string(),
+            },
+            ServerEvent::Room {
+                room_id: "room".to_string(),
+                member_count: 2,
+            },
+        ];
+        for event in events {
+            let json = event.to_json()?;
+            assert!(json.contains(&format!("\"type\":\"{}\"", event.event_type())));
+            assert_eq!(ServerEvent::from_json(&json)?, event);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let json = r#"{"version": 2, "type": "ack", "command": "UserUpdate"}"#;
+        assert!(ServerEvent::from_json(json).is_err());
+    }
+}
@@ -1,4 +1,7 @@
+#![allow(dead_code)]
+use super::errors::LogicError;
 use axum::body::Body;
+use axum::http::HeaderMap;
 use hyper::Request;
 use tracing_subscriber::{self};
 
@@ -18,3 +21,12 @@ pub fn trace_on_request(request: &Request<Body>, _: &tracing::Span) {
         route_key = %route_key,
     )
 }
+
+pub fn trace_on_rejected_handshake(headers: &HeaderMap, error: &LogicError) {
+    tracing::warn!(
+        origin = ?headers.get(axum::http::header::ORIGIN),
+        protocols = ?headers.get(axum::http::header::SEC_WEBSOCKET_PROTOCOL),
+        error = %error,
+        "rejected websocket handshake",
+    )
+}
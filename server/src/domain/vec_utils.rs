@@ -1,3 +1,4 @@
+#![allow(dead_code)]
 pub fn single<T>(vec: Vec<T>) -> Result<T, &'static str> {
     if vec.len() == 1 {
         Ok(vec.into_iter().next().unwrap())
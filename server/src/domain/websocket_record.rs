@@ -1,38 +1,87 @@
 #![allow(dead_code)]
+use super::connection_ttl::ConnectionTtl;
 use chrono::{DateTime, Utc};
+use dynamo_record_derive::DynamoRecord;
 
+#[derive(Clone, Debug, DynamoRecord)]
 pub struct WebsocketRecord {
     pub id: String,
+    pub user_id: String,
     pub room_id: String,
     pub name: String,
     pub modified_at: DateTime<Utc>,
+    // Epoch seconds after which DynamoDB deletes the record, in case the
+    // $disconnect never arrives. Records written before it existed are
+    // given one from their modified_at when read.
+    pub ttl: i64,
+    // The version that was read, which a save must still find stored.
+    // Records that have never been saved, or were saved before versions
+    // existed, are version 0.
+    #[dynamo(default)]
+    pub version: i64,
+}
+
+fn default_ttl() -> i64 {
+    ConnectionTtl::default().expires_at(Utc::now())
 }
 
 impl WebsocketRecord {
     pub fn new(id: &str) -> Self {
         WebsocketRecord {
             id: id.to_string(),
+            user_id: uuid::Uuid::new_v4().to_string(),
             room_id: uuid::Uuid::new_v4().to_string(),
             name: uuid::Uuid::new_v4().to_string(),
             modified_at: Utc::now(),
+            ttl: default_ttl(),
+            version: 0,
         }
     }
 
     pub fn new_with_room(id: &str, room_id: &str) -> Self {
         WebsocketRecord {
             id: id.to_string(),
+            user_id: uuid::Uuid::new_v4().to_string(),
             room_id: room_id.to_string(),
             name: uuid::Uuid::new_v4().to_string(),
             modified_at: Utc::now(),
+            ttl: default_ttl(),
+            version: 0,
         }
     }
 
     pub fn new_with_name(id: &str, name: &str) -> Self {
         WebsocketRecord {
             id: id.to_string(),
+            user_id: uuid::Uuid::new_v4().to_string(),
             room_id: uuid::Uuid::new_v4().to_string(),
             name: name.to_string(),
             modified_at: Utc::now(),
+            ttl: default_ttl(),
+            version: 0,
+        }
+    }
+
+    pub fn new_with_user(id: &str, user_id: &str) -> Self {
+        WebsocketRecord {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            room_id: uuid::Uuid::new_v4().to_string(),
+            name: uuid::Uuid::new_v4().to_string(),
+            modified_at: Utc::now(),
+            ttl: default_ttl(),
+            version: 0,
         }
     }
+
+    // Marks the connection as active, extending its TTL
+    pub fn touch(&mut self, ttl: &ConnectionTtl) {
+        let now = Utc::now();
+        self.modified_at = now;
+        self.ttl = ttl.expires_at(now);
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.ttl <= now.timestamp()
+    }
 }
@@ -0,0 +1,62 @@
+// Rewrites stored records that are behind their table's current schema
+// version. Records are also upcast when read, so this only needs to run
+// before an upcaster is removed.
+mod database;
+mod domain;
+
+use database::db_trait::IDatabase;
+use database::record_migration::migrate_table;
+use database::{
+    db_cloud::DatabaseCloud, db_local::DatabaseLocal, db_retry::DatabaseRetry,
+    db_sqlite::DatabaseSqlite,
+};
+use database::{rate_limit_table::RateLimitTable, websocket_table::WebsocketTable};
+use domain::tracing_utils;
+use std::env;
+use std::path::Path;
+use std::{error::Error, sync::Arc};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_utils::init_tracing();
+    let database = make_database().await?;
+    let tables = [
+        (
+            WebsocketTable::get_table_name(),
+            WebsocketTable::migrations(),
+        ),
+        (
+            RateLimitTable::get_table_name(),
+            RateLimitTable::migrations(),
+        ),
+    ];
+    for (table_name, registry) in tables {
+        tracing::info!(
+            table_name = %table_name,
+            current_version = registry.current_version(),
+            "migrating records"
+        );
+        let summary = migrate_table(&database, &table_name, &registry).await?;
+        tracing::info!(
+            scanned = summary.scanned,
+            migrated = summary.migrated,
+            skipped = summary.skipped,
+            "migration finished"
+        );
+    }
+    Ok(())
+}
+
+// The same databases as ws_handler_local, except that DynamoDB is the default
+async fn make_database() -> Result<Arc<dyn IDatabase>, Box<dyn Error + Send + Sync>> {
+    if let Ok(path) = env::var("SQLITE_PATH") {
+        return Ok(Arc::new(DatabaseSqlite::new(&path).await?));
+    }
+    if let Ok(dir) = env::var("LOCAL_DB_DIR") {
+        return Ok(Arc::new(
+            DatabaseLocal::with_persistence(Path::new(&dir)).await?,
+        ));
+    }
+    let cloud = Arc::new(DatabaseCloud::new().await);
+    Ok(Arc::new(DatabaseRetry::new(cloud)))
+}
@@ -0,0 +1,63 @@
+#![allow(dead_code)]
+use super::message_filter::{FilterOutcome, MessageFilter};
+use regex::Regex;
+
+// Rejects links to blocked domains and any of their subdomains
+pub struct LinkFilter {
+    blocked_domains: Vec<String>,
+    link_pattern: Regex,
+}
+
+impl LinkFilter {
+    pub fn new(blocked_domains: &[String]) -> Self {
+        let blocked_domains = blocked_domains
+            .iter()
+            .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+            .collect();
+        let link_pattern =
+            Regex::new(r"(?i)\b(?:[a-z][a-z0-9+.-]*://)?((?:[a-z0-9-]+\.)+[a-z]{2,})\b").unwrap();
+        LinkFilter {
+            blocked_domains,
+            link_pattern,
+        }
+    }
+
+    fn is_blocked(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.blocked_domains
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+    }
+}
+
+impl MessageFilter for LinkFilter {
+    fn apply(&self, text: &str) -> FilterOutcome {
+        for captures in self.link_pattern.captures_iter(text) {
+            let host = &captures[1];
+            if self.is_blocked(host) {
+                return FilterOutcome::Reject(format!("links to {} are not allowed", host));
+            }
+        }
+        FilterOutcome::Allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_blocked_domains_and_subdomains() {
+        let filter = LinkFilter::new(&["spam.com".to_string()]);
+        assert!(matches!(
+            filter.apply("see https://spam.com/offer"),
+            FilterOutcome::Reject(_)
+        ));
+        assert!(matches!(
+            filter.apply("see www.SPAM.com"),
+            FilterOutcome::Reject(_)
+        ));
+        assert_eq!(filter.apply("see notspam.com"), FilterOutcome::Allow);
+        assert_eq!(filter.apply("see example.com"), FilterOutcome::Allow);
+    }
+}
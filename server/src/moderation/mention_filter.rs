@@ -0,0 +1,48 @@
+#![allow(dead_code)]
+use super::message_filter::{FilterOutcome, MessageFilter};
+use regex::Regex;
+
+pub struct MentionFilter {
+    max_mentions: usize,
+    mention_pattern: Regex,
+}
+
+impl MentionFilter {
+    pub fn new(max_mentions: usize) -> Self {
+        let mention_pattern = Regex::new(r"(?:^|\s)@\w+").unwrap();
+        MentionFilter {
+            max_mentions,
+            mention_pattern,
+        }
+    }
+}
+
+impl MessageFilter for MentionFilter {
+    fn apply(&self, text: &str) -> FilterOutcome {
+        let mentions = self.mention_pattern.find_iter(text).count();
+        if mentions > self.max_mentions {
+            FilterOutcome::Reject(format!(
+                "too many mentions ({} > {})",
+                mentions, self.max_mentions
+            ))
+        } else {
+            FilterOutcome::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_too_many_mentions() {
+        let filter = MentionFilter::new(2);
+        assert_eq!(filter.apply("@a @b hi"), FilterOutcome::Allow);
+        assert_eq!(filter.apply("mail a@b.com @c"), FilterOutcome::Allow);
+        assert!(matches!(
+            filter.apply("@a @b @c hi"),
+            FilterOutcome::Reject(_)
+        ));
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(dead_code)]
+
+#[derive(Debug, PartialEq)]
+pub enum FilterOutcome {
+    Allow,
+    Rewrite(String),
+    Reject(String),
+}
+
+pub trait MessageFilter: Send + Sync {
+    fn apply(&self, text: &str) -> FilterOutcome;
+}
+
+// Runs each stage in order. Rewrites are passed on to later stages, and the
+// first rejection stops the pipeline.
+#[derive(Default)]
+pub struct FilterPipeline {
+    stages: Vec<Box<dyn MessageFilter>>,
+}
+
+impl FilterPipeline {
+    pub fn new(stages: Vec<Box<dyn MessageFilter>>) -> Self {
+        FilterPipeline { stages }
+    }
+
+    pub fn run(&self, text: &str) -> FilterOutcome {
+        let mut current = text.to_string();
+        for stage in &self.stages {
+            match stage.apply(&current) {
+                FilterOutcome::Allow => {}
+                FilterOutcome::Rewrite(rewritten) => current = rewritten,
+                FilterOutcome::Reject(reason) => return FilterOutcome::Reject(reason),
+            }
+        }
+        if current == text {
+            FilterOutcome::Allow
+        } else {
+            FilterOutcome::Rewrite(current)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Uppercase;
+
+    impl MessageFilter for Uppercase {
+        fn apply(&self, text: &str) -> FilterOutcome {
+            FilterOutcome::Rewrite(text.to_uppercase())
+        }
+    }
+
+    struct RejectContaining(&'static str);
+
+    impl MessageFilter for RejectContaining {
+        fn apply(&self, text: &str) -> FilterOutcome {
+            if text.contains(self.0) {
+                FilterOutcome::Reject("contains".to_string())
+            } else {
+                FilterOutcome::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_pipeline_allows() {
+        let pipeline = FilterPipeline::default();
+        assert_eq!(pipeline.run("hello"), FilterOutcome::Allow);
+    }
+
+    #[test]
+    fn test_rewrites_are_passed_to_later_stages() {
+        let pipeline =
+            FilterPipeline::new(vec![Box::new(Uppercase), Box::new(RejectContaining("HI"))]);
+        assert_eq!(pipeline.run("ok"), FilterOutcome::Rewrite("OK".to_string()));
+        assert_eq!(
+            pipeline.run("hi"),
+            FilterOutcome::Reject("contains".to_string())
+        );
+    }
+}
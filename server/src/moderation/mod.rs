@@ -0,0 +1,6 @@
+pub mod link_filter;
+pub mod mention_filter;
+pub mod message_filter;
+pub mod moderation_config;
+pub mod regex_filter;
+pub mod word_list_filter;
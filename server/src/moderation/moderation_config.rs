@@ -0,0 +1,122 @@
+#![allow(dead_code)]
+use super::link_filter::LinkFilter;
+use super::mention_filter::MentionFilter;
+use super::message_filter::{FilterPipeline, MessageFilter};
+use super::regex_filter::{RegexFilter, RegexRule};
+use super::word_list_filter::{FilterAction, WordListFilter};
+use crate::domain::errors::LogicError;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{env, fs};
+
+// Any rule left unset in a room falls back to the default rules
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ModerationRules {
+    pub blocked_words: Option<Vec<String>>,
+    pub blocked_word_action: Option<FilterAction>,
+    pub regex_rules: Option<Vec<RegexRule>>,
+    pub blocked_domains: Option<Vec<String>>,
+    pub max_mentions: Option<usize>,
+}
+
+impl ModerationRules {
+    pub fn overridden_by(&self, other: &ModerationRules) -> ModerationRules {
+        ModerationRules {
+            blocked_words: other.blocked_words.clone().or(self.blocked_words.clone()),
+            blocked_word_action: other.blocked_word_action.or(self.blocked_word_action),
+            regex_rules: other.regex_rules.clone().or(self.regex_rules.clone()),
+            blocked_domains: other
+                .blocked_domains
+                .clone()
+                .or(self.blocked_domains.clone()),
+            max_mentions: other.max_mentions.or(self.max_mentions),
+        }
+    }
+
+    pub fn build(&self) -> Result<FilterPipeline, LogicError> {
+        let mut stages: Vec<Box<dyn MessageFilter>> = vec![];
+        if let Some(words) = self.blocked_words.as_ref().filter(|w| !w.is_empty()) {
+            let action = self.blocked_word_action.unwrap_or_default();
+            stages.push(Box::new(WordListFilter::new(words, action)?));
+        }
+        for rule in self.regex_rules.iter().flatten() {
+            stages.push(Box::new(RegexFilter::new(rule)?));
+        }
+        if let Some(domains) = self.blocked_domains.as_ref().filter(|d| !d.is_empty()) {
+            stages.push(Box::new(LinkFilter::new(domains)));
+        }
+        if let Some(max_mentions) = self.max_mentions {
+            stages.push(Box::new(MentionFilter::new(max_mentions)));
+        }
+        Ok(FilterPipeline::new(stages))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ModerationConfig {
+    #[serde(default)]
+    pub default: ModerationRules,
+    #[serde(default)]
+    pub rooms: HashMap<String, ModerationRules>,
+}
+
+impl ModerationConfig {
+    pub fn compile(&self) -> Result<Moderator, LogicError> {
+        let default = self.default.build()?;
+        let mut rooms = HashMap::new();
+        for (room_id, rules) in &self.rooms {
+            let pipeline = self.default.overridden_by(rules).build()?;
+            rooms.insert(room_id.clone(), pipeline);
+        }
+        Ok(Moderator { default, rooms })
+    }
+}
+
+#[derive(Default)]
+pub struct Moderator {
+    default: FilterPipeline,
+    rooms: HashMap<String, FilterPipeline>,
+}
+
+impl Moderator {
+    // Reads a JSON ModerationConfig from the file at MODERATION_CONFIG_PATH
+    pub fn from_env() -> Self {
+        let Ok(path) = env::var("MODERATION_CONFIG_PATH") else {
+            return Moderator::default();
+        };
+        let json = fs::read_to_string(&path).expect("MODERATION_CONFIG_PATH is not readable");
+        let config: ModerationConfig =
+            serde_json::from_str(&json).expect("MODERATION_CONFIG_PATH is not valid");
+        config.compile().expect("moderation rules are not valid")
+    }
+
+    pub fn pipeline_for(&self, room_id: &str) -> &FilterPipeline {
+        self.rooms.get(room_id).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moderation::message_filter::FilterOutcome;
+
+    #[test]
+    fn test_rooms_override_default_rules() -> Result<(), LogicError> {
+        let json = r#"{
+            "default": {"blocked_words": ["darn"], "max_mentions": 1},
+            "rooms": {"strict": {"blocked_word_action": "reject", "max_mentions": 0}}
+        }"#;
+        let config: ModerationConfig = serde_json::from_str(json)?;
+        let moderator = config.compile()?;
+        let default = moderator.pipeline_for("lobby");
+        let strict = moderator.pipeline_for("strict");
+        assert_eq!(
+            default.run("darn"),
+            FilterOutcome::Rewrite("****".to_string())
+        );
+        assert_eq!(default.run("@a hi"), FilterOutcome::Allow);
+        assert!(matches!(strict.run("darn"), FilterOutcome::Reject(_)));
+        assert!(matches!(strict.run("@a hi"), FilterOutcome::Reject(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+#![allow(dead_code)]
+use super::message_filter::{FilterOutcome, MessageFilter};
+use super::word_list_filter::{mask, FilterAction};
+use crate::domain::errors::LogicError;
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegexRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub action: FilterAction,
+    pub reason: Option<String>,
+}
+
+pub struct RegexFilter {
+    pattern: Regex,
+    action: FilterAction,
+    reason: String,
+}
+
+impl RegexFilter {
+    pub fn new(rule: &RegexRule) -> Result<Self, LogicError> {
+        let pattern = Regex::new(&rule.pattern)
+            .map_err(|e| LogicError::InternalError(format!("invalid rule: {}", e)))?;
+        let reason = rule
+            .reason
+            .clone()
+            .unwrap_or_else(|| "matched a blocked pattern".to_string());
+        Ok(RegexFilter {
+            pattern,
+            action: rule.action,
+            reason,
+        })
+    }
+}
+
+impl MessageFilter for RegexFilter {
+    fn apply(&self, text: &str) -> FilterOutcome {
+        if !self.pattern.is_match(text) {
+            return FilterOutcome::Allow;
+        }
+        match self.action {
+            FilterAction::Reject => FilterOutcome::Reject(self.reason.clone()),
+            FilterAction::Mask => FilterOutcome::Rewrite(mask(&self.pattern, text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_with_reason() -> Result<(), LogicError> {
+        let rule = RegexRule {
+            pattern: r"\d{4}-\d{4}".to_string(),
+            action: FilterAction::Reject,
+            reason: Some("no card numbers".to_string()),
+        };
+        let filter = RegexFilter::new(&rule)?;
+        assert_eq!(
+            filter.apply("call 1234-5678"),
+            FilterOutcome::Reject("no card numbers".to_string())
+        );
+        assert_eq!(filter.apply("call me"), FilterOutcome::Allow);
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+#![allow(dead_code)]
+use super::message_filter::{FilterOutcome, MessageFilter};
+use crate::domain::errors::LogicError;
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    #[default]
+    Mask,
+    Reject,
+}
+
+// Matches whole words, ignoring case
+pub struct WordListFilter {
+    pattern: Regex,
+    action: FilterAction,
+}
+
+impl WordListFilter {
+    pub fn new(words: &[String], action: FilterAction) -> Result<Self, LogicError> {
+        let alternatives: Vec<String> = words.iter().map(|word| regex::escape(word)).collect();
+        let pattern = Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|")))
+            .map_err(|e| LogicError::InternalError(e.to_string()))?;
+        Ok(WordListFilter { pattern, action })
+    }
+}
+
+impl MessageFilter for WordListFilter {
+    fn apply(&self, text: &str) -> FilterOutcome {
+        if !self.pattern.is_match(text) {
+            return FilterOutcome::Allow;
+        }
+        match self.action {
+            FilterAction::Reject => FilterOutcome::Reject("blocked word".to_string()),
+            FilterAction::Mask => FilterOutcome::Rewrite(mask(&self.pattern, text)),
+        }
+    }
+}
+
+pub fn mask(pattern: &Regex, text: &str) -> String {
+    pattern
+        .replace_all(text, |captures: &regex::Captures| {
+            "*".repeat(captures[0].chars().count())
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks_whole_words() -> Result<(), LogicError> {
+        let filter = WordListFilter::new(&["darn".to_string()], FilterAction::Mask)?;
+        assert_eq!(
+            filter.apply("Darn it, darnation"),
+            FilterOutcome::Rewrite("**** it, darnation".to_string())
+        );
+        assert_eq!(filter.apply("hello"), FilterOutcome::Allow);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_words() -> Result<(), LogicError> {
+        let filter = WordListFilter::new(&["darn".to_string()], FilterAction::Reject)?;
+        assert!(matches!(filter.apply("darn"), FilterOutcome::Reject(_)));
+        Ok(())
+    }
+}
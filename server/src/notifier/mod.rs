@@ -2,3 +2,4 @@ pub mod notifier_cloud;
 pub mod notifier_fake;
 pub mod notifier_local;
 pub mod notifier_trait;
+pub mod send_all;
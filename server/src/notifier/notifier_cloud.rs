@@ -1,6 +1,7 @@
 #![allow(dead_code)]
-use super::notifier_trait::INotifier;
-use crate::domain::{errors::LogicError, message::Message};
+use super::notifier_trait::{Deliveries, INotifier};
+use super::send_all::send_all;
+use crate::domain::{errors::LogicError, fan_out_policy::FanOutPolicy, server_event::ServerEvent};
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
 use aws_sdk_apigatewaymanagement::{config::Region, primitives::Blob, Client};
 use axum::async_trait;
@@ -8,6 +9,7 @@ use std::env;
 
 pub struct NotifierCloud {
     client: Client,
+    policy: FanOutPolicy,
 }
 
 impl NotifierCloud {
@@ -22,29 +24,55 @@ impl NotifierCloud {
             .load()
             .await;
         let client = Client::new(&config);
-        NotifierCloud { client }
+        let policy = FanOutPolicy::from_env();
+        NotifierCloud { client, policy }
+    }
+
+    // The SDK takes ownership of the data, so each post copies the bytes
+    async fn post(&self, connection_id: &str, json: &str) -> Result<(), LogicError> {
+        self.client
+            .post_to_connection()
+            .connection_id(connection_id)
+            .data(Blob::new(json.as_bytes()))
+            .send()
+            .await
+            .map_err(|e| {
+                // API Gateway answers 410 once the client has disconnected
+                match e.as_service_error().map(|e| e.is_gone_exception()) {
+                    Some(true) => LogicError::ConnectionGone(connection_id.to_string()),
+                    _ => LogicError::WebsocketError(e.to_string()),
+                }
+            })?;
+        Ok(())
     }
 }
 
 #[async_trait]
 impl INotifier for NotifierCloud {
-    async fn notify(&self, connection_id: &str, message: &Message) -> Result<(), LogicError> {
+    async fn notify(&self, connection_id: &str, event: &ServerEvent) -> Result<(), LogicError> {
         tracing::info!(
-            "notifying connection {} with message {}",
+            "notifying connection {} with {} event",
             connection_id,
-            message.text
+            event.event_type()
         );
+        self.post(connection_id, &event.to_json()?).await
+    }
 
-        let message_json = serde_json::to_string(message)
-            .map_err(|e| LogicError::WebsocketError(e.to_string()))?;
-
-        self.client
-            .post_to_connection()
-            .connection_id(connection_id)
-            .data(Blob::new(message_json.as_bytes().to_vec()))
-            .send()
-            .await
-            .map_err(|e| LogicError::WebsocketError(e.to_string()))?;
-        Ok(())
+    async fn notify_many(
+        &self,
+        connection_ids: &[String],
+        event: &ServerEvent,
+    ) -> Result<Deliveries, LogicError> {
+        tracing::info!(
+            "notifying {} connections with {} event",
+            connection_ids.len(),
+            event.event_type()
+        );
+        let json = event.to_json()?;
+        let json = json.as_str();
+        let posts = send_all(connection_ids, &self.policy, |connection_id| async move {
+            self.post(&connection_id, json).await
+        });
+        Ok(posts.await)
     }
 }
@@ -1,19 +1,49 @@
 #![allow(dead_code)]
 use crate::domain::errors::LogicError;
-use crate::domain::message::Message;
-use crate::notifier::notifier_trait::INotifier;
+use crate::domain::server_event::ServerEvent;
+use crate::notifier::notifier_trait::{Deliveries, INotifier};
 use axum::async_trait;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
 
 pub struct NotifierFake {
     pub log: RwLock<HashMap<String, Vec<String>>>,
+    pub failing: RwLock<HashSet<String>>,
+    pub gone: RwLock<HashSet<String>>,
 }
 
 impl NotifierFake {
     pub async fn new() -> Self {
         let log = RwLock::new(HashMap::new());
-        NotifierFake { log }
+        let failing = RwLock::new(HashSet::new());
+        let gone = RwLock::new(HashSet::new());
+        NotifierFake { log, failing, gone }
+    }
+
+    // Later notifications to the connection fail without being logged
+    pub fn fail_connection(&self, connection_id: &str) {
+        let mut failing = self.failing.write().unwrap();
+        failing.insert(connection_id.to_string());
+    }
+
+    // Later notifications to the connection fail as if the client disconnected
+    pub fn disconnect(&self, connection_id: &str) {
+        let mut gone = self.gone.write().unwrap();
+        gone.insert(connection_id.to_string());
+    }
+
+    fn check_failing(&self, connection_id: &str) -> Result<(), LogicError> {
+        if self.gone.read().unwrap().contains(connection_id) {
+            return Err(LogicError::ConnectionGone(connection_id.to_string()));
+        }
+        let failing = self.failing.read().unwrap();
+        if failing.contains(connection_id) {
+            return Err(LogicError::WebsocketError(format!(
+                "{} is failing",
+                connection_id
+            )));
+        }
+        Ok(())
     }
 
     pub fn get_log(&self, connection_id: &str) -> Vec<String> {
@@ -21,22 +51,42 @@ impl NotifierFake {
         let default: Vec<String> = vec![];
         hash_map.get(connection_id).unwrap_or(&default).clone()
     }
-}
-
-#[async_trait]
-impl INotifier for NotifierFake {
-    async fn notify(&self, connection_id: &str, message: &Message) -> Result<(), LogicError> {
-        let message_json = serde_json::to_string(message)
-            .map_err(|e| LogicError::WebsocketError(e.to_string()))?;
 
+    fn append(&self, connection_id: &str, json: String) {
         let mut hash_map: std::sync::RwLockWriteGuard<'_, HashMap<String, Vec<String>>> =
             self.log.write().unwrap();
         match hash_map.get_mut(connection_id) {
-            Some(log) => log.push(message_json),
+            Some(log) => log.push(json),
             None => {
-                hash_map.insert(connection_id.to_string(), vec![message_json]);
+                hash_map.insert(connection_id.to_string(), vec![json]);
             }
         }
+    }
+}
+
+#[async_trait]
+impl INotifier for NotifierFake {
+    async fn notify(&self, connection_id: &str, event: &ServerEvent) -> Result<(), LogicError> {
+        self.check_failing(connection_id)?;
+        self.append(connection_id, event.to_json()?);
         Ok(())
     }
+
+    async fn notify_many(
+        &self,
+        connection_ids: &[String],
+        event: &ServerEvent,
+    ) -> Result<Deliveries, LogicError> {
+        let json = event.to_json()?;
+        let deliveries = connection_ids
+            .iter()
+            .map(|connection_id| {
+                let result = self
+                    .check_failing(connection_id)
+                    .map(|()| self.append(connection_id, json.clone()));
+                (connection_id.clone(), result)
+            })
+            .collect();
+        Ok(deliveries)
+    }
 }
@@ -1,50 +1,198 @@
 #![allow(dead_code)]
+use super::notifier_trait::{Deliveries, INotifier};
+use super::send_all::send_all;
 use crate::domain::errors::LogicError;
-use crate::domain::message::Message;
-use crate::notifier::notifier_trait::INotifier;
+use crate::domain::fan_out_policy::FanOutPolicy;
+use crate::domain::server_event::ServerEvent;
 use axum::async_trait;
 use axum::extract::ws::{Message as AxumMessage, WebSocket};
+use futures_util::stream::{SplitStream, StreamExt};
+use futures_util::{Sink, SinkExt};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
-use tokio::sync::Mutex;
+use tokio::sync::mpsc;
 
+// Frames queued for a client that is slower to read them than we are to send
+const OUTBOX_CAPACITY: usize = 64;
+
+// Each socket is split in two. The caller reads from one half, while a writer
+// task owns the other and sends whatever the notifier puts in its outbox, so
+// sending never waits for a read. Outboxes hold the serialized frame shared
+// between every recipient, and it is only copied when written to a socket.
 pub struct NotifierLocal {
-    pub sockets: RwLock<HashMap<String, Arc<Mutex<WebSocket>>>>,
+    pub outboxes: RwLock<HashMap<String, mpsc::Sender<Arc<str>>>>,
+    policy: FanOutPolicy,
 }
 
 impl NotifierLocal {
     pub async fn new() -> Self {
-        let sockets = RwLock::new(HashMap::new());
-        NotifierLocal { sockets }
+        let outboxes = RwLock::new(HashMap::new());
+        let policy = FanOutPolicy::from_env();
+        NotifierLocal { outboxes, policy }
+    }
+
+    // Returns the half of the socket that receives the client's messages
+    pub fn add_connection(
+        &self,
+        connection_id: &str,
+        websocket: WebSocket,
+    ) -> SplitStream<WebSocket> {
+        let (sink, stream) = websocket.split();
+        self.add_sink(connection_id, sink);
+        stream
     }
 
-    pub fn add_connection(&self, connection_id: &str, websocket: WebSocket) {
-        let arc_websocket = Arc::new(Mutex::new(websocket));
-        let mut sockets = self.sockets.write().unwrap();
-        sockets.insert(connection_id.to_string(), arc_websocket);
+    pub fn add_sink<S>(&self, connection_id: &str, mut sink: S)
+    where
+        S: Sink<AxumMessage> + Send + Unpin + 'static,
+    {
+        let (outbox, mut frames) = mpsc::channel::<Arc<str>>(OUTBOX_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(frame) = frames.recv().await {
+                if sink
+                    .send(AxumMessage::Text(frame.to_string()))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+        let mut outboxes = self.outboxes.write().unwrap();
+        outboxes.insert(connection_id.to_string(), outbox);
     }
 
-    pub fn get_connection(&self, connection_id: &str) -> Option<Arc<Mutex<WebSocket>>> {
-        let sockets = self.sockets.read().unwrap();
-        sockets.get(connection_id).cloned()
+    // Dropping the outbox stops the writer task, which closes the socket
+    pub fn remove_connection(&self, connection_id: &str) {
+        let mut outboxes = self.outboxes.write().unwrap();
+        outboxes.remove(connection_id);
+    }
+
+    async fn send(&self, connection_id: &str, frame: Arc<str>) -> Result<(), LogicError> {
+        let outbox = {
+            let outboxes = self.outboxes.read().unwrap();
+            outboxes.get(connection_id).cloned()
+        };
+        // Connections of other servers sharing the database are not ours to send to
+        let Some(outbox) = outbox else {
+            return Ok(());
+        };
+        outbox
+            .send(frame)
+            .await
+            .map_err(|_| LogicError::ConnectionGone(connection_id.to_string()))
     }
 }
 
 #[async_trait]
 impl INotifier for NotifierLocal {
-    async fn notify(&self, connection_id: &str, message: &Message) -> Result<(), LogicError> {
-        let message_json = serde_json::to_string(message)
-            .map_err(|e| LogicError::WebsocketError(e.to_string()))?;
-
-        let axum_message = AxumMessage::Text(message_json);
-        let socket = {
-            let sockets = self.sockets.read().unwrap();
-            sockets.get(connection_id).cloned()
-        };
-        if let Some(socket) = socket {
-            let mut socket = socket.lock().await;
-            let _ = socket.send(axum_message).await;
+    async fn notify(&self, connection_id: &str, event: &ServerEvent) -> Result<(), LogicError> {
+        self.send(connection_id, event.to_json()?.into()).await
+    }
+
+    async fn notify_many(
+        &self,
+        connection_ids: &[String],
+        event: &ServerEvent,
+    ) -> Result<Deliveries, LogicError> {
+        let frame: Arc<str> = event.to_json()?.into();
+        let sends = send_all(connection_ids, &self.policy, |connection_id| {
+            let frame = frame.clone();
+            async move { self.send(&connection_id, frame).await }
+        });
+        Ok(sends.await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::message::Message;
+    use futures_util::sink;
+    use std::convert::Infallible;
+
+    // A sink that forwards frames to a channel the test can read
+    fn channel_sink() -> (
+        impl Sink<AxumMessage> + Send + Unpin + 'static,
+        mpsc::UnboundedReceiver<AxumMessage>,
+    ) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let sink = sink::unfold(sender, |sender, frame| async move {
+            let _ = sender.send(frame);
+            Ok::<_, Infallible>(sender)
+        });
+        (Box::pin(sink), receiver)
+    }
+
+    fn message(text: &str) -> ServerEvent {
+        ServerEvent::Message(Message {
+            text: text.to_string(),
+            author_name: "name".to_string(),
+            sent_at: chrono::Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_sends_through_writer_task() -> Result<(), LogicError> {
+        let notifier = NotifierLocal::new().await;
+        let (sink, mut frames) = channel_sink();
+        notifier.add_sink("id1", sink);
+        notifier.notify("id1", &message("first")).await?;
+        notifier.notify("id1", &message("second")).await?;
+        for text in ["first", "second"] {
+            let Some(AxumMessage::Text(json)) = frames.recv().await else {
+                panic!("expected a text frame");
+            };
+            let ServerEvent::Message(received) = ServerEvent::from_json(&json)? else {
+                panic!("expected a message event");
+            };
+            assert_eq!(received.text, text);
         }
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_notify_many_reaches_every_connection() -> Result<(), LogicError> {
+        let notifier = NotifierLocal::new().await;
+        let (sink1, mut frames1) = channel_sink();
+        let (sink2, mut frames2) = channel_sink();
+        notifier.add_sink("id1", sink1);
+        notifier.add_sink("id2", sink2);
+        let connection_ids = vec!["id1".to_string(), "id2".to_string()];
+        let deliveries = notifier
+            .notify_many(&connection_ids, &message("hello"))
+            .await?;
+        assert!(deliveries.iter().all(|(_, result)| result.is_ok()));
+        let frame1 = frames1.recv().await;
+        let frame2 = frames2.recv().await;
+        assert!(frame1.is_some());
+        assert_eq!(frame1, frame2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_connection_is_ignored() {
+        let notifier = NotifierLocal::new().await;
+        assert!(notifier.notify("id1", &message("hello")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_closed_socket_is_gone() {
+        let notifier = NotifierLocal::new().await;
+        let failing = sink::unfold((), |_, _: AxumMessage| async { Err::<(), _>(()) });
+        notifier.add_sink("id1", Box::pin(failing));
+        // The first frame is accepted into the outbox before the writer fails
+        let _ = notifier.notify("id1", &message("first")).await;
+        let outbox = notifier
+            .outboxes
+            .read()
+            .unwrap()
+            .get("id1")
+            .cloned()
+            .unwrap();
+        outbox.closed().await;
+        let result = notifier.notify("id1", &message("again")).await;
+        assert!(matches!(result, Err(LogicError::ConnectionGone(_))));
+    }
 }
@@ -1,7 +1,17 @@
-use crate::domain::{errors::LogicError, message::Message};
+use crate::domain::{errors::LogicError, server_event::ServerEvent};
 use axum::async_trait;
 
+// The outcome of notifying each connection of a batch, in no particular order
+pub type Deliveries = Vec<(String, Result<(), LogicError>)>;
+
 #[async_trait]
 pub trait INotifier: Send + Sync {
-    async fn notify(&self, id: &str, message: &Message) -> Result<(), LogicError>;
+    async fn notify(&self, id: &str, event: &ServerEvent) -> Result<(), LogicError>;
+    // Serializes the event once for every connection. Only fails as a whole
+    // when the event cannot be serialized.
+    async fn notify_many(
+        &self,
+        ids: &[String],
+        event: &ServerEvent,
+    ) -> Result<Deliveries, LogicError>;
 }
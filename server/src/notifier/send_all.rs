@@ -0,0 +1,92 @@
+use super::notifier_trait::Deliveries;
+use crate::domain::errors::LogicError;
+use crate::domain::fan_out_policy::FanOutPolicy;
+use futures_util::stream::{self, StreamExt};
+use std::future::Future;
+
+// Runs `send` for every connection, up to max_concurrency at a time, so that
+// the whole batch takes about as long as its slowest recipient. A recipient
+// that fails or times out does not stop the others.
+pub async fn send_all<F, Fut>(
+    connection_ids: &[String],
+    policy: &FanOutPolicy,
+    send: F,
+) -> Deliveries
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(), LogicError>>,
+{
+    let timeout = policy.timeout;
+    // Built up front, since a stream combinator holding the closure would
+    // keep the returned future from being Send
+    let sends: Vec<_> = connection_ids
+        .iter()
+        .map(|connection_id| {
+            let sent = send(connection_id.clone());
+            let connection_id = connection_id.clone();
+            async move {
+                let result = match tokio::time::timeout(timeout, sent).await {
+                    Ok(result) => result,
+                    Err(_) => Err(LogicError::WebsocketError(format!(
+                        "timed out after {}ms",
+                        timeout.as_millis()
+                    ))),
+                };
+                (connection_id, result)
+            }
+        })
+        .collect();
+    stream::iter(sends)
+        .buffer_unordered(policy.max_concurrency.max(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn ids(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("id{}", i)).collect()
+    }
+
+    // Takes `delay` for anyone, and never finishes for "stuck"
+    async fn slow_send(connection_id: String, delay: Duration) -> Result<(), LogicError> {
+        if connection_id == "stuck" {
+            std::future::pending::<()>().await;
+        }
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_takes_as_long_as_slowest_recipient() {
+        let delay = Duration::from_millis(50);
+        let start = Instant::now();
+        let connection_ids = ids(20);
+        let policy = FanOutPolicy::default();
+        let results = send_all(&connection_ids, &policy, |id| slow_send(id, delay)).await;
+        assert_eq!(results.len(), 20);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert!(start.elapsed() < delay * 5);
+    }
+
+    #[tokio::test]
+    async fn test_stuck_recipient_times_out() {
+        let delay = Duration::from_millis(1);
+        let policy = FanOutPolicy {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut connection_ids = ids(2);
+        connection_ids.push("stuck".to_string());
+        let results = send_all(&connection_ids, &policy, |id| slow_send(id, delay)).await;
+        let failed: Vec<&String> = results
+            .iter()
+            .filter(|(_, result)| result.is_err())
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(failed, vec!["stuck"]);
+    }
+}
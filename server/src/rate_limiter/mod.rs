@@ -0,0 +1,3 @@
+pub mod rate_limiter_cloud;
+pub mod rate_limiter_local;
+pub mod rate_limiter_trait;
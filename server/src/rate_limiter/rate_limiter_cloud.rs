@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+use super::rate_limiter_trait::IRateLimiter;
+use crate::database::{db_trait::IDatabase, rate_limit_table::RateLimitTable};
+use crate::domain::{
+    errors::LogicError,
+    rate_limit::{try_take_all, RateLimit, RateLimitOutcome, TokenBucket},
+};
+use axum::async_trait;
+use chrono::Utc;
+use std::sync::Arc;
+
+// Buckets are shared between lambda instances through the database. Concurrent
+// requests for the same key may both read the same bucket, so a burst can
+// slightly exceed the limit.
+pub struct RateLimiterCloud {
+    database: Arc<dyn IDatabase>,
+}
+
+impl RateLimiterCloud {
+    pub async fn new(database: Arc<dyn IDatabase>) -> Self {
+        RateLimiterCloud { database }
+    }
+}
+
+#[async_trait]
+impl IRateLimiter for RateLimiterCloud {
+    async fn acquire(&self, keys: &[(String, RateLimit)]) -> Result<RateLimitOutcome, LogicError> {
+        let now = Utc::now();
+        let mut buckets = Vec::with_capacity(keys.len());
+        for (key, limit) in keys {
+            let bucket = RateLimitTable::from_db(key, &self.database)
+                .await?
+                .unwrap_or_else(|| TokenBucket::full(limit, now));
+            buckets.push((bucket, limit.clone()));
+        }
+        let outcome = try_take_all(&mut buckets, now);
+        // Nothing was taken from a limited command's buckets
+        if outcome == RateLimitOutcome::Allowed {
+            let keys = keys.iter().map(|(key, _)| key.as_str());
+            RateLimitTable::to_db(keys.zip(&buckets), &self.database).await?;
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::db_local::DatabaseLocal;
+
+    #[tokio::test]
+    async fn test_buckets_persist_between_instances() -> Result<(), LogicError> {
+        let limit = RateLimit {
+            capacity: 1.0,
+            refill_per_second: 0.001,
+        };
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let first = RateLimiterCloud::new(db.clone()).await;
+        let second = RateLimiterCloud::new(db.clone()).await;
+        let keys = [("key".to_string(), limit)];
+        assert_eq!(first.acquire(&keys).await?, RateLimitOutcome::Allowed);
+        let outcome = second.acquire(&keys).await?;
+        assert!(matches!(outcome, RateLimitOutcome::Limited { .. }));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limited_command_takes_no_tokens() -> Result<(), LogicError> {
+        let plenty = RateLimit {
+            capacity: 2.0,
+            refill_per_second: 0.001,
+        };
+        let none = RateLimit {
+            capacity: 0.0,
+            refill_per_second: 0.001,
+        };
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let rate_limiter = RateLimiterCloud::new(db).await;
+        let keys = [
+            ("first".to_string(), plenty.clone()),
+            ("second".to_string(), none),
+        ];
+        let outcome = rate_limiter.acquire(&keys).await?;
+        assert!(matches!(outcome, RateLimitOutcome::Limited { .. }));
+        let first = [("first".to_string(), plenty)];
+        assert_eq!(
+            rate_limiter.acquire(&first).await?,
+            RateLimitOutcome::Allowed
+        );
+        assert_eq!(
+            rate_limiter.acquire(&first).await?,
+            RateLimitOutcome::Allowed
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+#![allow(dead_code)]
+use super::rate_limiter_trait::IRateLimiter;
+use crate::domain::{
+    errors::LogicError,
+    rate_limit::{try_take_all, RateLimit, RateLimitOutcome, TokenBucket},
+};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const MIN_SWEEP_SIZE: usize = 1024;
+
+#[derive(Default)]
+struct Buckets {
+    // Each with the limit it was last used with, to tell when it is full
+    buckets: HashMap<String, (TokenBucket, RateLimit)>,
+    // Full buckets are dropped whenever the map grows to this size
+    sweep_at: usize,
+}
+
+impl Buckets {
+    fn sweep(&mut self, now: DateTime<Utc>) {
+        if self.buckets.len() < self.sweep_at {
+            return;
+        }
+        self.buckets
+            .retain(|_, (bucket, limit)| !bucket.is_full(limit, now));
+        self.sweep_at = (self.buckets.len() * 2).max(MIN_SWEEP_SIZE);
+    }
+}
+
+pub struct RateLimiterLocal {
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiterLocal {
+    pub async fn new() -> Self {
+        let buckets = Mutex::new(Buckets {
+            buckets: HashMap::new(),
+            sweep_at: MIN_SWEEP_SIZE,
+        });
+        RateLimiterLocal { buckets }
+    }
+
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().buckets.len()
+    }
+}
+
+#[async_trait]
+impl IRateLimiter for RateLimiterLocal {
+    async fn acquire(&self, keys: &[(String, RateLimit)]) -> Result<RateLimitOutcome, LogicError> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.sweep(now);
+        let mut taken: Vec<(TokenBucket, RateLimit)> = keys
+            .iter()
+            .map(|(key, limit)| {
+                let bucket = match buckets.buckets.get(key) {
+                    Some((bucket, _)) => bucket.clone(),
+                    None => TokenBucket::full(limit, now),
+                };
+                (bucket, limit.clone())
+            })
+            .collect();
+        let outcome = try_take_all(&mut taken, now);
+        for ((key, _), entry) in keys.iter().zip(taken) {
+            buckets.buckets.insert(key.clone(), entry);
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_full_buckets_are_evicted() -> Result<(), LogicError> {
+        let limit = RateLimit {
+            capacity: 1.0,
+            refill_per_second: 1000.0,
+        };
+        let rate_limiter = RateLimiterLocal::new().await;
+        for i in 0..MIN_SWEEP_SIZE {
+            let keys = [(format!("key{}", i), limit.clone())];
+            rate_limiter.acquire(&keys).await?;
+        }
+        assert_eq!(rate_limiter.len(), MIN_SWEEP_SIZE);
+        // Every bucket refills within a millisecond, so the next sweep drops them all
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        rate_limiter.acquire(&[("last".to_string(), limit)]).await?;
+        assert_eq!(rate_limiter.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+use crate::domain::{
+    errors::LogicError,
+    rate_limit::{RateLimit, RateLimitOutcome},
+};
+use axum::async_trait;
+
+#[async_trait]
+pub trait IRateLimiter: Send + Sync {
+    // Takes a token from the bucket of every key, or from none if any is empty
+    async fn acquire(
+        &self,
+        buckets: &[(String, RateLimit)],
+    ) -> Result<RateLimitOutcome, LogicError>;
+}
@@ -0,0 +1,108 @@
+#![allow(dead_code)]
+use super::connection_repository_trait::IConnectionRepository;
+use crate::database::{
+    db_local::DatabaseLocal, db_trait::IDatabase, websocket_table::WebsocketTable,
+};
+use crate::domain::{errors::LogicError, websocket_record::WebsocketRecord};
+use axum::async_trait;
+use std::sync::Arc;
+
+// Stores connections as DynamoDB items, through any IDatabase
+pub struct ConnectionRepositoryDynamo {
+    database: Arc<dyn IDatabase>,
+}
+
+impl ConnectionRepositoryDynamo {
+    pub async fn new(database: Arc<dyn IDatabase>) -> Self {
+        ConnectionRepositoryDynamo { database }
+    }
+
+    // Kept in memory by DatabaseLocal, which applies the same conditions and
+    // TTL as DynamoDB
+    pub async fn in_memory() -> Self {
+        Self::new(Arc::new(DatabaseLocal::new().await)).await
+    }
+}
+
+#[async_trait]
+impl IConnectionRepository for ConnectionRepositoryDynamo {
+    async fn get(&self, connection_id: &str) -> Result<WebsocketRecord, LogicError> {
+        WebsocketTable::from_db(connection_id, &self.database).await
+    }
+
+    async fn save(&self, record: &mut WebsocketRecord) -> Result<(), LogicError> {
+        WebsocketTable::to_db(record, &self.database).await
+    }
+
+    async fn delete(&self, record: &WebsocketRecord) -> Result<(), LogicError> {
+        let transaction = WebsocketTable::delete(record)?;
+        self.database.write_single(transaction).await
+    }
+
+    async fn get_room_connections(
+        &self,
+        room_id: &str,
+    ) -> Result<Vec<WebsocketRecord>, LogicError> {
+        WebsocketTable::get_room_connections(room_id, &self.database).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::db_local::DatabaseLocal;
+
+    #[tokio::test]
+    async fn test_round_trips_through_database() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let repository = ConnectionRepositoryDynamo::new(db).await;
+        let mut record = WebsocketRecord::new_with_room("id1", "room");
+        repository.save(&mut record).await?;
+        assert_eq!(repository.get("id1").await?.room_id, "room");
+        assert_eq!(repository.get_room_connections("room").await?.len(), 1);
+        repository.delete(&record).await?;
+        assert!(repository.get("id1").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves_conflict() -> Result<(), LogicError> {
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
+        let repository = ConnectionRepositoryDynamo::new(db).await;
+        repository.save(&mut WebsocketRecord::new("id1")).await?;
+        let mut first = repository.get("id1").await?;
+        let mut second = repository.get("id1").await?;
+        first.name = "first".to_string();
+        second.name = "second".to_string();
+        repository.save(&mut first).await?;
+        let result = repository.save(&mut second).await;
+        assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+        let stored = repository.get("id1").await?;
+        assert_eq!(stored.name, "first");
+        assert_eq!(stored.version, 2);
+        // A record saved again after its first save needs no re-read
+        first.name = "again".to_string();
+        repository.save(&mut first).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hides_expired_records() -> Result<(), LogicError> {
+        // Without TTL on the table, like DynamoDB before its sweep runs
+        let mut schema = WebsocketTable::schema();
+        schema.ttl_attribute = None;
+        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::with_schemas(vec![schema]).await);
+        let repository = ConnectionRepositoryDynamo::new(db).await;
+        let mut record = WebsocketRecord::new_with_room("id1", "room");
+        record.ttl = chrono::Utc::now().timestamp() - 1;
+        repository.save(&mut record).await?;
+        repository
+            .save(&mut WebsocketRecord::new_with_room("id2", "room"))
+            .await?;
+        assert!(repository.get("id1").await.is_err());
+        let records = repository.get_room_connections("room").await?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "id2");
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+use crate::domain::{errors::LogicError, websocket_record::WebsocketRecord};
+use axum::async_trait;
+
+#[async_trait]
+pub trait IConnectionRepository: Send + Sync {
+    // Fails with a DatabaseError when the connection is not stored
+    async fn get(&self, connection_id: &str) -> Result<WebsocketRecord, LogicError>;
+    // Fails with ConditionalCheckFailed when the record was saved by someone
+    // else since it was read. On success the record's version is bumped, so
+    // it can be saved again.
+    async fn save(&self, record: &mut WebsocketRecord) -> Result<(), LogicError>;
+    async fn delete(&self, record: &WebsocketRecord) -> Result<(), LogicError>;
+    async fn get_room_connections(&self, room_id: &str)
+        -> Result<Vec<WebsocketRecord>, LogicError>;
+}
@@ -0,0 +1,2 @@
+pub mod connection_repository_dynamo;
+pub mod connection_repository_trait;
@@ -0,0 +1,77 @@
+use crate::domain::errors::LogicError;
+use crate::domain::server_event::ServerEvent;
+use crate::notifier::notifier_trait::INotifier;
+use std::sync::Arc;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DeliverySummary {
+    pub delivered: Vec<String>,
+    // Connections whose clients have disconnected
+    pub gone: Vec<String>,
+    pub failed: Vec<(String, LogicError)>,
+}
+
+// Notifies every connection in one batch. A failed recipient is recorded in
+// the summary and does not stop delivery to the others.
+pub async fn fan_out(
+    connection_ids: Vec<String>,
+    event: &ServerEvent,
+    notifier: &Arc<dyn INotifier>,
+) -> Result<DeliverySummary, LogicError> {
+    let deliveries = notifier.notify_many(&connection_ids, event).await?;
+    let mut summary = DeliverySummary::default();
+    for (connection_id, result) in deliveries {
+        match result {
+            Ok(()) => summary.delivered.push(connection_id),
+            Err(LogicError::ConnectionGone(_)) => summary.gone.push(connection_id),
+            Err(e) => summary.failed.push((connection_id, e)),
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::message::Message;
+    use crate::notifier::notifier_fake::NotifierFake;
+
+    fn message() -> ServerEvent {
+        ServerEvent::Message(Message {
+            text: "hello".to_string(),
+            author_name: "name".to_string(),
+            sent_at: chrono::Utc::now(),
+        })
+    }
+
+    fn ids(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("id{}", i)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_continues_past_failures() -> Result<(), LogicError> {
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        notifier_fake.fail_connection("id1");
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone();
+        let summary = fan_out(ids(3), &message(), &notifier).await?;
+        let mut delivered = summary.delivered.clone();
+        delivered.sort();
+        assert_eq!(delivered, vec!["id0", "id2"]);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, "id1");
+        assert_eq!(notifier_fake.get_log("id2").len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reports_gone_connections() -> Result<(), LogicError> {
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        notifier_fake.disconnect("id0");
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone();
+        let summary = fan_out(ids(2), &message(), &notifier).await?;
+        assert_eq!(summary.delivered, vec!["id1"]);
+        assert_eq!(summary.gone, vec!["id0"]);
+        assert!(summary.failed.is_empty());
+        Ok(())
+    }
+}
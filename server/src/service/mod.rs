@@ -1,3 +1,9 @@
+pub mod fan_out;
 pub mod on_connect;
 pub mod on_disconnect;
+pub mod on_heartbeat;
 pub mod on_message;
+pub mod rate_limit;
+pub mod remove_gone;
+pub mod service_config;
+pub mod update_record;
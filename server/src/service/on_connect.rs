@@ -1,29 +1,35 @@
-use crate::database::{db_trait::IDatabase, websocket_table::WebsocketTable};
+use super::service_config::ServiceConfig;
 use crate::domain::{errors::LogicError, websocket_record::WebsocketRecord};
+use crate::repository::connection_repository_trait::IConnectionRepository;
 use std::sync::Arc;
 
 pub async fn on_connect(
     connection_id: &str,
-    database: &Arc<dyn IDatabase>,
+    user_id: &str,
+    connections: &Arc<dyn IConnectionRepository>,
+    config: &ServiceConfig,
 ) -> Result<(), LogicError> {
     tracing::info!("on_connect!");
-    let record = WebsocketRecord::new(connection_id);
-    let transaction = WebsocketTable::save(&record)?;
-    database.write_single(transaction).await
+    let mut record = WebsocketRecord::new_with_user(connection_id, user_id);
+    record.touch(&config.connection_ttl);
+    connections.save(&mut record).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::db_local::DatabaseLocal;
+    use crate::repository::connection_repository_dynamo::ConnectionRepositoryDynamo;
 
     #[tokio::test]
     async fn test_creates_new_record() {
         let id = "test";
-        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
-        let result = on_connect(id, &db).await;
+        let user_id = "user";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        let result = on_connect(id, user_id, &connections, &ServiceConfig::default()).await;
         assert!(result.is_ok());
-        let record = WebsocketTable::from_db(id, &db).await;
+        let record = connections.get(id).await;
         assert!(record.is_ok());
+        assert_eq!(record.unwrap().user_id, user_id);
     }
 }
@@ -0,0 +1,121 @@
+use super::rate_limit::check_rate_limit;
+use super::service_config::ServiceConfig;
+use super::update_record::update_record;
+use crate::domain::errors::LogicError;
+use crate::domain::rate_limit::{CommandKind, RateLimitOutcome};
+use crate::rate_limiter::rate_limiter_trait::IRateLimiter;
+use crate::repository::connection_repository_trait::IConnectionRepository;
+use std::sync::Arc;
+
+// Clients send heartbeats while idle so their record does not expire. Like
+// other activity, a heartbeat only rewrites the record once it needs a refresh,
+// and one over the limit is dropped without a reply.
+pub async fn on_heartbeat(
+    connection_id: &str,
+    connections: &Arc<dyn IConnectionRepository>,
+    rate_limiter: &Arc<dyn IRateLimiter>,
+    config: &ServiceConfig,
+) -> Result<(), LogicError> {
+    tracing::debug!("on_heartbeat!");
+    let record = connections.get(connection_id).await?;
+    let outcome = check_rate_limit(
+        CommandKind::Heartbeat,
+        &record,
+        &config.rate_limits,
+        rate_limiter,
+    )
+    .await?;
+    if let RateLimitOutcome::Limited { retry_after_ms } = outcome {
+        tracing::info!("heartbeat rate limited, retry after {}ms", retry_after_ms);
+        return Ok(());
+    }
+    if config
+        .connection_ttl
+        .needs_refresh(record.ttl, chrono::Utc::now())
+    {
+        update_record(connection_id, connections, |record| {
+            record.touch(&config.connection_ttl)
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::rate_limit::{CommandRateLimits, RateLimit, RateLimitConfig};
+    use crate::domain::websocket_record::WebsocketRecord;
+    use crate::rate_limiter::rate_limiter_local::RateLimiterLocal;
+    use crate::repository::connection_repository_dynamo::ConnectionRepositoryDynamo;
+    use std::collections::HashMap;
+
+    async fn save_record(
+        connections: &Arc<dyn IConnectionRepository>,
+        id: &str,
+        ttl: i64,
+    ) -> Result<(), LogicError> {
+        let mut record = WebsocketRecord::new(id);
+        record.ttl = ttl;
+        connections.save(&mut record).await
+    }
+
+    #[tokio::test]
+    async fn test_extends_ttl() -> Result<(), LogicError> {
+        let id = "test";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let about_to_expire = chrono::Utc::now().timestamp() + 1;
+        save_record(&connections, id, about_to_expire).await?;
+        on_heartbeat(id, &connections, &rate_limiter, &ServiceConfig::default()).await?;
+        let record = connections.get(id).await?;
+        assert!(record.ttl > about_to_expire);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fresh_ttl_is_not_rewritten() -> Result<(), LogicError> {
+        let id = "test";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        let fresh = config.connection_ttl.expires_at(chrono::Utc::now()) - 1;
+        save_record(&connections, id, fresh).await?;
+        on_heartbeat(id, &connections, &rate_limiter, &config).await?;
+        assert_eq!(connections.get(id).await?.ttl, fresh);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_over_limit_is_dropped() -> Result<(), LogicError> {
+        let id = "test";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let limits = CommandRateLimits {
+            connection: Some(RateLimit {
+                capacity: 1.0,
+                refill_per_second: 0.01,
+            }),
+            user: None,
+        };
+        let config = ServiceConfig {
+            rate_limits: RateLimitConfig {
+                commands: HashMap::from([(CommandKind::Heartbeat, limits)]),
+            },
+            ..Default::default()
+        };
+        let about_to_expire = chrono::Utc::now().timestamp() + 1;
+        save_record(&connections, id, about_to_expire).await?;
+        on_heartbeat(id, &connections, &rate_limiter, &config).await?;
+        // The first heartbeat spent the only token, so this one does not refresh
+        let mut record = connections.get(id).await?;
+        record.ttl = about_to_expire;
+        connections.save(&mut record).await?;
+        on_heartbeat(id, &connections, &rate_limiter, &config).await?;
+        assert_eq!(connections.get(id).await?.ttl, about_to_expire);
+        Ok(())
+    }
+}
@@ -1,42 +1,121 @@
-use crate::database::{db_trait::IDatabase, websocket_table::WebsocketTable};
+use super::fan_out::fan_out;
+use super::on_heartbeat::on_heartbeat;
+use super::rate_limit::check_rate_limit;
+use super::remove_gone::remove_gone;
+use super::service_config::ServiceConfig;
+use super::update_record::update_record;
+use crate::domain::error_frame::ErrorFrame;
 use crate::domain::errors::LogicError;
 use crate::domain::message::Message;
+use crate::domain::message_limits::MessageLimits;
+use crate::domain::rate_limit::{CommandKind, RateLimitOutcome};
+use crate::domain::server_event::ServerEvent;
+use crate::moderation::message_filter::FilterOutcome;
 use crate::notifier::notifier_trait::INotifier;
+use crate::rate_limiter::rate_limiter_trait::IRateLimiter;
+use crate::repository::connection_repository_trait::IConnectionRepository;
 use std::sync::Arc;
 
 const USER_UPDATE_PREFIX: &str = "UserUpdate:";
+const HEARTBEAT_PREFIX: &str = "Heartbeat:";
 
 pub async fn on_message(
     connection_id: &str,
     text: &str,
     notifier: &Arc<dyn INotifier>,
-    database: &Arc<dyn IDatabase>,
+    connections: &Arc<dyn IConnectionRepository>,
+    rate_limiter: &Arc<dyn IRateLimiter>,
+    config: &ServiceConfig,
 ) -> Result<(), LogicError> {
     tracing::info!("on_message!");
-    if text.starts_with(USER_UPDATE_PREFIX) {
+    if text.starts_with(HEARTBEAT_PREFIX) {
+        return on_heartbeat(connection_id, connections, rate_limiter, config).await;
+    }
+    let kind = if text.starts_with(USER_UPDATE_PREFIX) {
+        CommandKind::Join
+    } else {
+        CommandKind::Message
+    };
+    if let Some(error) = check_size(kind, text, &config.message_limits) {
+        tracing::info!("message too large: {}", error.detail);
+        return notifier
+            .notify(connection_id, &ServerEvent::Error(error))
+            .await;
+    }
+    let record = connections.get(connection_id).await?;
+    let outcome = check_rate_limit(kind, &record, &config.rate_limits, rate_limiter).await?;
+    if let RateLimitOutcome::Limited { retry_after_ms } = outcome {
+        tracing::info!("rate limited, retry after {}ms", retry_after_ms);
+        let error = ErrorFrame::rate_limited(retry_after_ms);
+        return notifier
+            .notify(connection_id, &ServerEvent::Error(error))
+            .await;
+    }
+    if kind == CommandKind::Join {
         let raw = text.trim_start_matches(USER_UPDATE_PREFIX);
         let (room_id, name) = parse_user_update_request(raw)?;
-        let mut record = WebsocketTable::from_db(&connection_id, database).await?;
-        record.room_id = room_id;
-        record.name = name;
-        let transaction = WebsocketTable::save(&record)?;
-        database.write_single(transaction).await?;
+        update_record(connection_id, connections, |record| {
+            record.room_id = room_id.clone();
+            record.name = name.clone();
+            record.touch(&config.connection_ttl);
+        })
+        .await?;
         return Ok(());
     }
-    let record = WebsocketTable::from_db(&connection_id, database).await?;
-    let message = Message {
-        text: text.to_string(),
+    if config
+        .connection_ttl
+        .needs_refresh(record.ttl, chrono::Utc::now())
+    {
+        update_record(connection_id, connections, |record| {
+            record.touch(&config.connection_ttl)
+        })
+        .await?;
+    }
+    let text = match config.moderation.pipeline_for(&record.room_id).run(text) {
+        FilterOutcome::Allow => text.to_string(),
+        FilterOutcome::Rewrite(rewritten) => rewritten,
+        FilterOutcome::Reject(reason) => {
+            tracing::info!("message rejected: {}", reason);
+            let error = ErrorFrame::message_rejected(&reason);
+            return notifier
+                .notify(connection_id, &ServerEvent::Error(error))
+                .await;
+        }
+    };
+    let event = ServerEvent::Message(Message {
+        text,
         author_name: record.name,
         sent_at: chrono::Utc::now(),
-    };
-    let records = WebsocketTable::get_room_connections(&record.room_id, database).await?;
-    for record in records {
-        notifier.notify(&record.id, &message).await?;
+    });
+    let records = connections.get_room_connections(&record.room_id).await?;
+    let connection_ids = records.into_iter().map(|record| record.id).collect();
+    let summary = fan_out(connection_ids, &event, notifier).await?;
+    for (connection_id, error) in &summary.failed {
+        tracing::warn!("could not notify {}: {}", connection_id, error);
     }
-
+    tracing::info!(
+        delivered = summary.delivered.len(),
+        gone = summary.gone.len(),
+        failed = summary.failed.len(),
+        "delivered message to room {}",
+        record.room_id
+    );
+    remove_gone(&summary.gone, connections).await;
     Ok(())
 }
 
+fn check_size(kind: CommandKind, text: &str, limits: &MessageLimits) -> Option<ErrorFrame> {
+    if text.len() > limits.max_frame_bytes {
+        let detail = format!("frame exceeds {} bytes", limits.max_frame_bytes);
+        return Some(ErrorFrame::message_too_large(&detail));
+    }
+    if kind == CommandKind::Message && text.chars().count() > limits.max_text_length {
+        let detail = format!("text exceeds {} characters", limits.max_text_length);
+        return Some(ErrorFrame::message_too_large(&detail));
+    }
+    None
+}
+
 fn parse_user_update_request(text: &str) -> Result<(String, String), LogicError> {
     // Expect a string in the form of "RoomId=room&Name=name"
     // We update both in a single message to avoid race conditions if we were
@@ -63,25 +142,47 @@ fn parse_user_update_request(text: &str) -> Result<(String, String), LogicError>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::db_local::DatabaseLocal;
+    use crate::domain::error_frame::{MESSAGE_REJECTED, MESSAGE_TOO_LARGE, RATE_LIMITED};
+    use crate::domain::rate_limit::{CommandRateLimits, RateLimit, RateLimitConfig};
     use crate::domain::websocket_record::WebsocketRecord;
+    use crate::moderation::moderation_config::ModerationConfig;
     use crate::notifier::notifier_fake::NotifierFake;
     use crate::notifier::notifier_local::NotifierLocal;
+    use crate::rate_limiter::rate_limiter_local::RateLimiterLocal;
+    use crate::repository::connection_repository_dynamo::ConnectionRepositoryDynamo;
     use serde_json::from_str;
+    use std::collections::HashMap;
     use std::sync::Arc;
 
+    fn parse_message(json: &str) -> Message {
+        match ServerEvent::from_json(json) {
+            Ok(ServerEvent::Message(message)) => message,
+            other => panic!("expected a message, got {:?}", other),
+        }
+    }
+
+    fn parse_error(json: &str) -> ErrorFrame {
+        match ServerEvent::from_json(json) {
+            Ok(ServerEvent::Error(error)) => error,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
     #[tokio::test]
     async fn test_user_change_updates_record() -> Result<(), LogicError> {
         let id = "test";
         let room_id = "room";
         let name = "name";
         let text = format!("{}RoomId={}&Name={}", USER_UPDATE_PREFIX, room_id, name);
-        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
-        WebsocketTable::to_db(&WebsocketRecord::new(id), &db).await?;
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections.save(&mut WebsocketRecord::new(id)).await?;
         let notifier: Arc<dyn INotifier> = Arc::new(NotifierLocal::new().await);
-        let result = on_message(id, &text, &notifier, &db).await;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        let result = on_message(id, &text, &notifier, &connections, &rate_limiter, &config).await;
         assert!(result.is_ok());
-        let record = WebsocketTable::from_db(id, &db).await?;
+        let record = connections.get(id).await?;
         assert!(record.room_id == room_id);
         assert!(record.name == name);
         Ok(())
@@ -95,26 +196,298 @@ mod tests {
         let id3 = "test3";
         let room = "room";
         let text = "hello";
-        let db: Arc<dyn IDatabase> = Arc::new(DatabaseLocal::new().await);
-        db.write(vec![
-            WebsocketTable::save(&WebsocketRecord::new_with_room(id1, room))?,
-            WebsocketTable::save(&WebsocketRecord::new_with_room(id2, room))?,
-            WebsocketTable::save(&WebsocketRecord::new(id3))?,
-        ])
-        .await?;
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id1, room))
+            .await?;
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id2, room))
+            .await?;
+        connections.save(&mut WebsocketRecord::new(id3)).await?;
         let notifier_fake = Arc::new(NotifierFake::new().await);
         let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
-        on_message(id1, &text, &notifier, &db).await?;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        on_message(id1, text, &notifier, &connections, &rate_limiter, &config).await?;
         let log1 = notifier_fake.get_log(id1);
         let log2 = notifier_fake.get_log(id2);
         let log3 = notifier_fake.get_log(id3);
         assert_eq!(log1.len(), 1);
         assert_eq!(log2.len(), 1);
         assert_eq!(log3.len(), 0);
-        let message1: Message = from_str(&log1[0])?;
-        let message2: Message = from_str(&log1[0])?;
+        let message1 = parse_message(&log1[0]);
+        let message2 = parse_message(&log2[0]);
         assert_eq!(message1.text, text);
         assert_eq!(message2.text, text);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_failed_recipient_does_not_stop_delivery() -> Result<(), LogicError> {
+        let room = "room";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        for id in ["test1", "test2", "test3"] {
+            connections
+                .save(&mut WebsocketRecord::new_with_room(id, room))
+                .await?;
+        }
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        notifier_fake.fail_connection("test1");
+        notifier_fake.fail_connection("test2");
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        on_message(
+            "test1",
+            "hello",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        assert_eq!(notifier_fake.get_log("test3").len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gone_recipient_is_removed_from_room() -> Result<(), LogicError> {
+        let room = "room";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        for id in ["test1", "test2"] {
+            connections
+                .save(&mut WebsocketRecord::new_with_room(id, room))
+                .await?;
+        }
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        notifier_fake.disconnect("test2");
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        on_message(
+            "test1",
+            "hello",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        assert_eq!(notifier_fake.get_log("test1").len(), 1);
+        let remaining = connections.get_room_connections(room).await?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, "test1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_message_over_limit_is_rejected() -> Result<(), LogicError> {
+        let id1 = "test1";
+        let id2 = "test2";
+        let room = "room";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id1, room))
+            .await?;
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id2, room))
+            .await?;
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let limits = CommandRateLimits {
+            connection: Some(RateLimit {
+                capacity: 1.0,
+                refill_per_second: 1.0,
+            }),
+            user: None,
+        };
+        let config = ServiceConfig {
+            rate_limits: RateLimitConfig {
+                commands: HashMap::from([(CommandKind::Message, limits)]),
+            },
+            ..Default::default()
+        };
+        on_message(
+            id1,
+            "first",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        on_message(
+            id1,
+            "second",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        let log1 = notifier_fake.get_log(id1);
+        let log2 = notifier_fake.get_log(id2);
+        assert_eq!(log1.len(), 2);
+        assert_eq!(log2.len(), 1);
+        let error = parse_error(&log1[1]);
+        assert_eq!(error.error, RATE_LIMITED);
+        assert!(error.retry_after_ms.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_moderation_rewrites_and_rejects() -> Result<(), LogicError> {
+        let id1 = "test1";
+        let id2 = "test2";
+        let room = "room";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id1, room))
+            .await?;
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id2, room))
+            .await?;
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let json = r#"{"default": {"blocked_words": ["darn"], "blocked_domains": ["spam.com"]}}"#;
+        let moderation: ModerationConfig = from_str(json)?;
+        let config = ServiceConfig {
+            moderation: moderation.compile()?,
+            ..Default::default()
+        };
+        on_message(
+            id1,
+            "darn it",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        on_message(
+            id1,
+            "go to spam.com",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        let log1 = notifier_fake.get_log(id1);
+        let log2 = notifier_fake.get_log(id2);
+        assert_eq!(log1.len(), 2);
+        assert_eq!(log2.len(), 1);
+        let message = parse_message(&log2[0]);
+        assert_eq!(message.text, "**** it");
+        let error = parse_error(&log1[1]);
+        assert_eq!(error.error, MESSAGE_REJECTED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_message_over_size_limits_is_rejected() -> Result<(), LogicError> {
+        let id = "test";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections.save(&mut WebsocketRecord::new(id)).await?;
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig {
+            message_limits: MessageLimits {
+                max_text_length: 5,
+                max_frame_bytes: 10,
+            },
+            ..Default::default()
+        };
+        on_message(
+            id,
+            "abcdef",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        let name_update = format!("{}RoomId=room&Name=name", USER_UPDATE_PREFIX);
+        on_message(
+            id,
+            &name_update,
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        let log = notifier_fake.get_log(id);
+        assert_eq!(log.len(), 2);
+        for json in log {
+            let error = parse_error(&json);
+            assert_eq!(error.error, MESSAGE_TOO_LARGE);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_activity_refreshes_stale_ttl() -> Result<(), LogicError> {
+        let id = "test";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        let mut record = WebsocketRecord::new(id);
+        record.ttl = chrono::Utc::now().timestamp() + 1;
+        connections.save(&mut record).await?;
+        let notifier: Arc<dyn INotifier> = Arc::new(NotifierFake::new().await);
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        on_message(id, "hello", &notifier, &connections, &rate_limiter, &config).await?;
+        let refreshed = connections.get(id).await?.ttl;
+        assert!(refreshed > record.ttl);
+        // A fresh TTL is not rewritten by every message
+        on_message(id, "again", &notifier, &connections, &rate_limiter, &config).await?;
+        assert_eq!(connections.get(id).await?.ttl, refreshed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_word_is_a_chat_message() -> Result<(), LogicError> {
+        let id = "test";
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections
+            .save(&mut WebsocketRecord::new_with_room(id, "room"))
+            .await?;
+        let notifier_fake = Arc::new(NotifierFake::new().await);
+        let notifier: Arc<dyn INotifier> = notifier_fake.clone() as Arc<dyn INotifier>;
+        let rate_limiter: Arc<dyn IRateLimiter> = Arc::new(RateLimiterLocal::new().await);
+        let config = ServiceConfig::default();
+        on_message(
+            id,
+            "Heartbeat",
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        on_message(
+            id,
+            HEARTBEAT_PREFIX,
+            &notifier,
+            &connections,
+            &rate_limiter,
+            &config,
+        )
+        .await?;
+        let log = notifier_fake.get_log(id);
+        assert_eq!(log.len(), 1);
+        assert_eq!(parse_message(&log[0]).text, "Heartbeat");
+        Ok(())
+    }
 }
@@ -0,0 +1,29 @@
+use crate::domain::{
+    errors::LogicError,
+    rate_limit::{CommandKind, RateLimitConfig, RateLimitOutcome},
+    websocket_record::WebsocketRecord,
+};
+use crate::rate_limiter::rate_limiter_trait::IRateLimiter;
+use std::sync::Arc;
+
+// Checks the connection bucket and the user bucket shared by all of the user's
+// connections together, so a command limited by either spends neither
+pub async fn check_rate_limit(
+    kind: CommandKind,
+    record: &WebsocketRecord,
+    config: &RateLimitConfig,
+    rate_limiter: &Arc<dyn IRateLimiter>,
+) -> Result<RateLimitOutcome, LogicError> {
+    let limits = config.for_command(kind);
+    let mut keys = Vec::new();
+    if let Some(limit) = limits.connection {
+        keys.push((format!("connection#{}#{}", record.id, kind.as_str()), limit));
+    }
+    if let Some(limit) = limits.user {
+        keys.push((format!("user#{}#{}", record.user_id, kind.as_str()), limit));
+    }
+    if keys.is_empty() {
+        return Ok(RateLimitOutcome::Allowed);
+    }
+    rate_limiter.acquire(&keys).await
+}
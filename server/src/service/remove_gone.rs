@@ -0,0 +1,36 @@
+use super::on_disconnect::on_disconnect;
+use crate::repository::connection_repository_trait::IConnectionRepository;
+use std::sync::Arc;
+
+// Cleans up connections whose clients left without a $disconnect reaching us,
+// exactly as if it had arrived. Failures are only logged, since the record
+// may already have been removed by a late $disconnect or its TTL.
+pub async fn remove_gone(connection_ids: &[String], connections: &Arc<dyn IConnectionRepository>) {
+    for connection_id in connection_ids {
+        tracing::info!("removing gone connection {}", connection_id);
+        if let Err(e) = on_disconnect(connection_id, connections).await {
+            tracing::info!("could not remove gone connection {}: {}", connection_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::errors::LogicError;
+    use crate::domain::websocket_record::WebsocketRecord;
+    use crate::repository::connection_repository_dynamo::ConnectionRepositoryDynamo;
+
+    #[tokio::test]
+    async fn test_removes_records_and_skips_missing() -> Result<(), LogicError> {
+        let connections: Arc<dyn IConnectionRepository> =
+            Arc::new(ConnectionRepositoryDynamo::in_memory().await);
+        connections
+            .save(&mut WebsocketRecord::new_with_room("test1", "room"))
+            .await?;
+        let ids = vec!["missing".to_string(), "test1".to_string()];
+        remove_gone(&ids, &connections).await;
+        assert!(connections.get("test1").await.is_err());
+        Ok(())
+    }
+}
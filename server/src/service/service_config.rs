@@ -0,0 +1,24 @@
+#![allow(dead_code)]
+use crate::domain::{
+    connection_ttl::ConnectionTtl, message_limits::MessageLimits, rate_limit::RateLimitConfig,
+};
+use crate::moderation::moderation_config::Moderator;
+
+#[derive(Default)]
+pub struct ServiceConfig {
+    pub rate_limits: RateLimitConfig,
+    pub moderation: Moderator,
+    pub message_limits: MessageLimits,
+    pub connection_ttl: ConnectionTtl,
+}
+
+impl ServiceConfig {
+    pub fn from_env() -> Self {
+        ServiceConfig {
+            rate_limits: RateLimitConfig::from_env(),
+            moderation: Moderator::from_env(),
+            message_limits: MessageLimits::from_env(),
+            connection_ttl: ConnectionTtl::from_env(),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::domain::{errors::LogicError, websocket_record::WebsocketRecord};
+use crate::repository::connection_repository_trait::IConnectionRepository;
+use std::sync::Arc;
+
+const MAX_ATTEMPTS: usize = 3;
+
+// Reads the record, applies `update` and saves it. When another request saved
+// the record in between, the update is applied again to the fresh record, and
+// after MAX_ATTEMPTS the ConditionalCheckFailed error is returned.
+pub async fn update_record<F>(
+    connection_id: &str,
+    connections: &Arc<dyn IConnectionRepository>,
+    update: F,
+) -> Result<WebsocketRecord, LogicError>
+where
+    F: Fn(&mut WebsocketRecord) + Send + Sync,
+{
+    let mut attempt = 1;
+    loop {
+        let mut record = connections.get(connection_id).await?;
+        update(&mut record);
+        match connections.save(&mut record).await {
+            Ok(()) => return Ok(record),
+            Err(LogicError::ConditionalCheckFailed(reason)) if attempt < MAX_ATTEMPTS => {
+                tracing::info!("retrying update of {}: {}", connection_id, reason);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::connection_repository_dynamo::ConnectionRepositoryDynamo;
+    use axum::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Saves the record behind the caller's back on the first `conflicts` reads
+    struct Interleaved {
+        inner: ConnectionRepositoryDynamo,
+        conflicts: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl IConnectionRepository for Interleaved {
+        async fn get(&self, connection_id: &str) -> Result<WebsocketRecord, LogicError> {
+            let record = self.inner.get(connection_id).await?;
+            if self.conflicts.load(Ordering::SeqCst) > 0 {
+                self.conflicts.fetch_sub(1, Ordering::SeqCst);
+                let mut other = record.clone();
+                other.name = "other".to_string();
+                self.inner.save(&mut other).await?;
+            }
+            Ok(record)
+        }
+
+        async fn save(&self, record: &mut WebsocketRecord) -> Result<(), LogicError> {
+            self.inner.save(record).await
+        }
+
+        async fn delete(&self, record: &WebsocketRecord) -> Result<(), LogicError> {
+            self.inner.delete(record).await
+        }
+
+        async fn get_room_connections(
+            &self,
+            room_id: &str,
+        ) -> Result<Vec<WebsocketRecord>, LogicError> {
+            self.inner.get_room_connections(room_id).await
+        }
+    }
+
+    async fn interleaved(conflicts: usize) -> Result<Arc<dyn IConnectionRepository>, LogicError> {
+        let inner = ConnectionRepositoryDynamo::in_memory().await;
+        inner.save(&mut WebsocketRecord::new("id")).await?;
+        let conflicts = AtomicUsize::new(conflicts);
+        Ok(Arc::new(Interleaved { inner, conflicts }))
+    }
+
+    #[tokio::test]
+    async fn test_retries_after_conflict() -> Result<(), LogicError> {
+        let connections = interleaved(2).await?;
+        let record = update_record("id", &connections, |record| {
+            record.room_id = "room".to_string();
+        })
+        .await?;
+        assert_eq!(record.version, 4);
+        let stored = connections.get("id").await?;
+        assert_eq!(stored.room_id, "room");
+        assert_eq!(stored.name, "other");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() -> Result<(), LogicError> {
+        let connections = interleaved(MAX_ATTEMPTS).await?;
+        let result = update_record("id", &connections, |record| {
+            record.room_id = "room".to_string();
+        })
+        .await;
+        assert!(matches!(result, Err(LogicError::ConditionalCheckFailed(_))));
+        Ok(())
+    }
+}
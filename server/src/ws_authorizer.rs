@@ -0,0 +1,134 @@
+mod domain {
+    pub mod auth_token;
+    pub mod errors;
+    pub mod tracing_utils;
+}
+
+use domain::{auth_token::TokenValidator, errors::LogicError, tracing_utils};
+use lambda_http::aws_lambda_events::apigw::{
+    ApiGatewayCustomAuthorizerPolicy, ApiGatewayCustomAuthorizerRequestTypeRequest,
+    ApiGatewayCustomAuthorizerResponse,
+};
+use lambda_http::aws_lambda_events::iam::{IamPolicyEffect, IamPolicyStatement};
+use lambda_http::{lambda_runtime, service_fn, LambdaEvent};
+use serde_json::json;
+use std::{error::Error, sync::Arc};
+
+const TOKEN_QUERY_PARAMETER: &str = "token";
+const USER_ID_CONTEXT_KEY: &str = "userId";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    tracing_utils::init_tracing();
+    let validator = Arc::new(TokenValidator::from_env());
+    lambda_runtime::run(service_fn(
+        move |event: LambdaEvent<ApiGatewayCustomAuthorizerRequestTypeRequest>| {
+            let validator = validator.clone();
+            async move {
+                handle_authorize(event.payload, &validator)
+                    .await
+                    .map_err(lambda_http::Error::from)
+            }
+        },
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn handle_authorize(
+    request: ApiGatewayCustomAuthorizerRequestTypeRequest,
+    validator: &TokenValidator,
+) -> Result<ApiGatewayCustomAuthorizerResponse, LogicError> {
+    let method_arn = request
+        .method_arn
+        .clone()
+        .ok_or(LogicError::BadRequest("no method arn".to_string()))?;
+    let response = match authorize(&request, validator) {
+        Ok(user_id) => {
+            tracing::info!(user_id = %user_id, "allowing connection");
+            build_response(&user_id, IamPolicyEffect::Allow, &method_arn)
+        }
+        Err(e) => {
+            tracing::info!(error = %e, "denying connection");
+            build_response("anonymous", IamPolicyEffect::Deny, &method_arn)
+        }
+    };
+    Ok(response)
+}
+
+fn authorize(
+    request: &ApiGatewayCustomAuthorizerRequestTypeRequest,
+    validator: &TokenValidator,
+) -> Result<String, LogicError> {
+    let token = request
+        .query_string_parameters
+        .first(TOKEN_QUERY_PARAMETER)
+        .ok_or(LogicError::Unauthorized("no token".to_string()))?;
+    validator.validate(token)
+}
+
+fn build_response(
+    user_id: &str,
+    effect: IamPolicyEffect,
+    method_arn: &str,
+) -> ApiGatewayCustomAuthorizerResponse {
+    let statement = IamPolicyStatement {
+        action: vec!["execute-api:Invoke".to_string()],
+        effect,
+        resource: vec![method_arn.to_string()],
+        condition: None,
+    };
+    ApiGatewayCustomAuthorizerResponse {
+        principal_id: Some(user_id.to_string()),
+        policy_document: ApiGatewayCustomAuthorizerPolicy {
+            version: Some("2012-10-17".to_string()),
+            statement: vec![statement],
+        },
+        context: json!({ USER_ID_CONTEXT_KEY: user_id }),
+        usage_identifier_key: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn make_request(token: Option<&str>) -> ApiGatewayCustomAuthorizerRequestTypeRequest {
+        let mut query = HashMap::new();
+        if let Some(token) = token {
+            query.insert(TOKEN_QUERY_PARAMETER.to_string(), token.to_string());
+        }
+        ApiGatewayCustomAuthorizerRequestTypeRequest {
+            method_arn: Some("arn:aws:execute-api:region:account:api/stage/$connect".to_string()),
+            query_string_parameters: query.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allows_valid_token() -> Result<(), LogicError> {
+        let validator = TokenValidator::new("secret");
+        let request = make_request(Some(&validator.issue("user1")));
+        let response = handle_authorize(request, &validator).await?;
+        let statement = &response.policy_document.statement[0];
+        assert_eq!(statement.effect, IamPolicyEffect::Allow);
+        assert_eq!(response.context[USER_ID_CONTEXT_KEY], "user1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_denies_missing_or_invalid_token() -> Result<(), LogicError> {
+        let validator = TokenValidator::new("secret");
+        let other = TokenValidator::new("other");
+        for request in [
+            make_request(None),
+            make_request(Some(&other.issue("user1"))),
+        ] {
+            let response = handle_authorize(request, &validator).await?;
+            let statement = &response.policy_document.statement[0];
+            assert_eq!(statement.effect, IamPolicyEffect::Deny);
+        }
+        Ok(())
+    }
+}
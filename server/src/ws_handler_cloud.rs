@@ -1,22 +1,33 @@
 mod database;
 mod domain;
+mod moderation;
 mod notifier;
+mod rate_limiter;
+mod repository;
 mod service;
 
-use axum::{body::Body, extract::State, http::Request, routing::any, Router};
-use database::{db_cloud::DatabaseCloud, db_trait::IDatabase};
-use domain::{errors::LogicError, tracing_utils};
-use lambda_http::{
-    aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequestContext, request::RequestContext,
-    RequestExt,
-};
+use axum::http::{header, HeaderMap, HeaderValue, Request};
+use axum::{body::Body, extract::State, routing::any, Router};
+use database::{db_cache::DatabaseCache, db_cloud::DatabaseCloud};
+use database::{db_retry::DatabaseRetry, db_trait::IDatabase};
+use domain::{errors::LogicError, handshake_policy::HandshakePolicy, tracing_utils};
+use lambda_http::{request::RequestContext, RequestExt};
 use notifier::{notifier_cloud::NotifierCloud, notifier_trait::INotifier};
+use rate_limiter::{rate_limiter_cloud::RateLimiterCloud, rate_limiter_trait::IRateLimiter};
+use repository::{
+    connection_repository_dynamo::ConnectionRepositoryDynamo,
+    connection_repository_trait::IConnectionRepository,
+};
+use service::service_config::ServiceConfig;
 use std::{error::Error, sync::Arc};
 use tower_http::trace::TraceLayer;
 
 struct AppState {
-    database: Arc<dyn IDatabase>,
+    connections: Arc<dyn IConnectionRepository>,
     notifier: Arc<dyn INotifier>,
+    rate_limiter: Arc<dyn IRateLimiter>,
+    config: Arc<ServiceConfig>,
+    handshake_policy: HandshakePolicy,
 }
 
 #[tokio::main]
@@ -36,68 +47,123 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 }
 
 async fn make_state() -> Arc<AppState> {
+    let cloud = Arc::new(DatabaseCloud::new().await);
+    let database: Arc<dyn IDatabase> = Arc::new(DatabaseRetry::new(cloud));
+    // Kept across invocations of a warm Lambda. Rate limit buckets are
+    // written on every message, so they skip the cache.
+    let cached: Arc<dyn IDatabase> = Arc::new(DatabaseCache::from_env(database.clone()));
     Arc::new(AppState {
-        database: Arc::new(DatabaseCloud::new().await),
+        rate_limiter: Arc::new(RateLimiterCloud::new(database).await),
+        connections: Arc::new(ConnectionRepositoryDynamo::new(cached).await),
         notifier: Arc::new(NotifierCloud::new().await),
+        config: Arc::new(ServiceConfig::from_env()),
+        handshake_policy: HandshakePolicy::from_env(),
     })
 }
 
+const USER_ID_CONTEXT_KEY: &str = "userId";
+const API_GATEWAY_MAX_MESSAGE_BYTES: usize = 128 * 1024;
+
+struct WebsocketContext {
+    route_key: String,
+    request_id: String,
+    connection_id: String,
+    user_id: String,
+}
+
 #[axum::debug_handler]
 async fn handle_websocket(
     State(state): State<Arc<AppState>>,
     request: Request<Body>,
-) -> Result<(), LogicError> {
-    let database = state.database.clone();
+) -> Result<HeaderMap, LogicError> {
+    let connections = state.connections.clone();
     let notifier = state.notifier.clone();
+    let rate_limiter = state.rate_limiter.clone();
     let context = parse_context(&request.request_context()).await?;
+    let mut response_headers = HeaderMap::new();
+    if state.handshake_policy.offers_protocol(request.headers()) {
+        let protocol = HeaderValue::from_str(&state.handshake_policy.required_protocol)
+            .map_err(|e| LogicError::InternalError(e.to_string()))?;
+        response_headers.insert(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+    }
     let message = parse_body(request.into_body()).await?;
-    let route_key = context
-        .route_key
-        .ok_or(LogicError::BadRequest("no route key".to_string()))?;
-    let request_id = context
-        .request_id
-        .ok_or(LogicError::BadRequest("no request id".to_string()))?;
-    let connection_id = context
-        .connection_id
-        .ok_or(LogicError::BadRequest("no connection id".to_string()))?;
+    let WebsocketContext {
+        route_key,
+        request_id,
+        connection_id,
+        user_id,
+    } = context;
 
     tracing::info!(
         route_key = %route_key,
         request_id = %request_id,
         connection_id = %connection_id,
+        user_id = %user_id,
         message = %message,
     );
     match route_key.as_str() {
         "$connect" => {
-            service::on_connect::on_connect(&connection_id, &database).await?;
+            service::on_connect::on_connect(&connection_id, &user_id, &connections, &state.config)
+                .await?;
         }
         "$disconnect" => {
-            service::on_disconnect::on_disconnect(&connection_id, &database).await?;
+            service::on_disconnect::on_disconnect(&connection_id, &connections).await?;
         }
         "$default" => {
-            service::on_message::on_message(&connection_id, &message, &notifier, &database).await?;
+            service::on_message::on_message(
+                &connection_id,
+                &message,
+                &notifier,
+                &connections,
+                &rate_limiter,
+                &state.config,
+            )
+            .await?;
         }
         _ => return Err(LogicError::BadRequest("unrecognised route key".to_string())),
     }
-    Ok(())
+    // Only read on $connect, where it completes the handshake
+    Ok(response_headers)
 }
 
-async fn parse_context(
-    ctx: &RequestContext,
-) -> Result<ApiGatewayWebsocketProxyRequestContext, LogicError> {
-    // let ctx_str = serde_json::to_string(&ctx)
-    //     .map_err(|_| LogicError::BadRequest("cant parse context".to_string()))?;
-    match ctx {
-        RequestContext::WebSocket(ctx) => {
-            return Ok(ctx.clone());
-        }
+async fn parse_context(ctx: &RequestContext) -> Result<WebsocketContext, LogicError> {
+    let ctx = match ctx {
+        RequestContext::WebSocket(ctx) => ctx,
         _ => return Err(LogicError::BadRequest("bad context".to_string())),
-    }
+    };
+    let route_key = ctx
+        .route_key
+        .clone()
+        .ok_or(LogicError::BadRequest("no route key".to_string()))?;
+    let request_id = ctx
+        .request_id
+        .clone()
+        .ok_or(LogicError::BadRequest("no request id".to_string()))?;
+    let connection_id = ctx
+        .connection_id
+        .clone()
+        .ok_or(LogicError::BadRequest("no connection id".to_string()))?;
+    // Populated by the ws_authorizer lambda when the connection is established
+    let user_id = ctx
+        .authorizer
+        .fields
+        .get(USER_ID_CONTEXT_KEY)
+        .and_then(|value| value.as_str())
+        .ok_or(LogicError::Unauthorized("no user id".to_string()))?
+        .to_string();
+    Ok(WebsocketContext {
+        route_key,
+        request_id,
+        connection_id,
+        user_id,
+    })
 }
 
 async fn parse_body(body: Body) -> Result<String, LogicError> {
-    let limit = 2048usize;
-    let bytes = axum::body::to_bytes(body, limit)
+    // Configured message limits are enforced by the service layer, so that the
+    // client receives an error frame. This only guards against bodies larger
+    // than API Gateway can deliver.
+    let bytes = axum::body::to_bytes(body, API_GATEWAY_MAX_MESSAGE_BYTES)
         .await
         .map_err(|_| LogicError::BadRequest("cant parse body".to_string()))?;
     let body_str = String::from_utf8(bytes.to_vec())
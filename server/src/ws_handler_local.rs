@@ -1,26 +1,49 @@
 mod database;
 mod domain;
+mod moderation;
 mod notifier;
+mod rate_limiter;
+mod repository;
 mod service;
 
-use axum::extract::ws::{Message, WebSocketUpgrade};
-use axum::extract::State;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
 use axum::response::Response;
 use axum::{routing::any, Router};
-use database::db_cloud::DatabaseCloud;
-use database::{db_local::DatabaseLocal, db_trait::IDatabase};
-use domain::{errors::LogicError, tracing_utils};
-use futures_util::stream::StreamExt;
+use database::{db_cache::DatabaseCache, db_cloud::DatabaseCloud, db_retry::DatabaseRetry};
+use database::{db_local::DatabaseLocal, db_sqlite::DatabaseSqlite, db_trait::IDatabase};
+use domain::{
+    auth_token::TokenValidator, errors::LogicError, handshake_policy::HandshakePolicy,
+    tracing_utils,
+};
+use futures_util::stream::{SplitStream, StreamExt};
 use notifier::{notifier_local::NotifierLocal, notifier_trait::INotifier};
+use rate_limiter::{rate_limiter_local::RateLimiterLocal, rate_limiter_trait::IRateLimiter};
+use repository::{
+    connection_repository_dynamo::ConnectionRepositoryDynamo,
+    connection_repository_trait::IConnectionRepository,
+};
+use serde::Deserialize;
+use service::service_config::ServiceConfig;
 use std::env;
+use std::path::Path;
 use std::{error::Error, sync::Arc};
-use tokio::time;
 use tower_http::trace::TraceLayer;
 use uuid::Uuid;
 
 struct AppState {
-    database: Arc<dyn IDatabase>,
+    connections: Arc<dyn IConnectionRepository>,
     notifier: Arc<NotifierLocal>,
+    rate_limiter: Arc<dyn IRateLimiter>,
+    config: Arc<ServiceConfig>,
+    token_validator: TokenValidator,
+    handshake_policy: HandshakePolicy,
+}
+
+#[derive(Deserialize)]
+struct ConnectParams {
+    token: Option<String>,
 }
 
 #[tokio::main]
@@ -40,73 +63,114 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
 async fn make_state() -> Arc<AppState> {
     let region_name = env::var("WEBSOCKET_TABLE_NAME");
-    let database: Arc<dyn IDatabase> = match region_name {
-        Ok(_) => Arc::new(DatabaseCloud::new().await),
-        Err(_) => Arc::new(DatabaseLocal::new().await),
+    let sqlite_path = env::var("SQLITE_PATH");
+    let local_dir = env::var("LOCAL_DB_DIR");
+    let database: Arc<dyn IDatabase> = match (region_name, sqlite_path, local_dir) {
+        (Ok(_), _, _) => Arc::new(DatabaseRetry::new(Arc::new(DatabaseCloud::new().await))),
+        (Err(_), Ok(path), _) => Arc::new(
+            DatabaseSqlite::new(&path)
+                .await
+                .expect("could not open SQLITE_PATH"),
+        ),
+        (Err(_), Err(_), Ok(dir)) => Arc::new(
+            DatabaseLocal::with_persistence(Path::new(&dir))
+                .await
+                .expect("could not restore LOCAL_DB_DIR"),
+        ),
+        (Err(_), Err(_), Err(_)) => Arc::new(DatabaseLocal::new().await),
     };
+    let cached = Arc::new(DatabaseCache::from_env(database));
+    let connections: Arc<dyn IConnectionRepository> =
+        Arc::new(ConnectionRepositoryDynamo::new(cached).await);
     Arc::new(AppState {
         notifier: Arc::new(NotifierLocal::new().await),
-        database,
+        connections,
+        rate_limiter: Arc::new(RateLimiterLocal::new().await),
+        config: Arc::new(ServiceConfig::from_env()),
+        token_validator: TokenValidator::from_env(),
+        handshake_policy: HandshakePolicy::from_env(),
     })
 }
 
 #[axum::debug_handler]
 async fn initialise_connection(
     State(state): State<Arc<AppState>>,
+    Query(params): Query<ConnectParams>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
 ) -> Result<Response, LogicError> {
-    let database = state.database.clone();
-    let notifier = state.notifier.clone();
+    let user_id = match authorize_handshake(&state, &headers, params.token) {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            tracing_utils::trace_on_rejected_handshake(&headers, &e);
+            return Err(e);
+        }
+    };
     let request_id = Uuid::new_v4().to_string();
-    service::on_connect::on_connect(&request_id, &database).await?;
+    service::on_connect::on_connect(&request_id, &user_id, &state.connections, &state.config)
+        .await?;
+    // Frames over the limit are dropped by the websocket before being buffered
+    let max_frame_bytes = state.config.message_limits.max_frame_bytes;
+    let ws = ws
+        .protocols([state.handshake_policy.required_protocol.clone()])
+        .max_frame_size(max_frame_bytes)
+        .max_message_size(max_frame_bytes);
     let response = ws.on_upgrade(move |socket| async move {
-        notifier.add_connection(&request_id, socket);
-        if let Err(e) = handle_socket(&request_id, notifier, database).await {
+        let messages = state.notifier.add_connection(&request_id, socket);
+        if let Err(e) = handle_socket(&request_id, messages, state).await {
             tracing::error!("Error handling socket: {:?}", e);
         }
     });
     Ok(response)
 }
 
+// Returns the user the handshake's token was issued to
+fn authorize_handshake(
+    state: &AppState,
+    headers: &HeaderMap,
+    token: Option<String>,
+) -> Result<String, LogicError> {
+    state.handshake_policy.validate(headers)?;
+    let token = token.ok_or(LogicError::Unauthorized("no token".to_string()))?;
+    state.token_validator.validate(&token)
+}
+
 async fn handle_socket(
     connection_id: &str,
-    notifier_local: Arc<NotifierLocal>,
-    database: Arc<dyn IDatabase>,
+    mut messages: SplitStream<WebSocket>,
+    state: Arc<AppState>,
 ) -> Result<(), LogicError> {
-    let notifier: Arc<dyn INotifier> = notifier_local.clone() as Arc<dyn INotifier>;
-    loop {
-        tokio::select! {
-            msg = wait_for_message(connection_id, &notifier_local) => {
-                if let Some(Ok(msg)) = msg {
-                    if let Message::Text(text) = msg {
-                        let result =
-                            service::on_message::on_message(connection_id, &text, &notifier, &database)
-                                .await;
-                        if result.is_err() {
-                            break;
-                        }
-                    }
-                } else {
-                    break;
-                }
-            },
-            _ = time::sleep(time::Duration::from_millis(200)) => {
-                // This branch unlocks the socket so we can receive messages
+    let notifier: Arc<dyn INotifier> = state.notifier.clone() as Arc<dyn INotifier>;
+    while let Some(Ok(msg)) = messages.next().await {
+        let result = match msg {
+            Message::Text(text) => {
+                service::on_message::on_message(
+                    connection_id,
+                    &text,
+                    &notifier,
+                    &state.connections,
+                    &state.rate_limiter,
+                    &state.config,
+                )
+                .await
+            }
+            // axum answers pings itself, they only keep the record alive
+            Message::Ping(_) => {
+                service::on_heartbeat::on_heartbeat(
+                    connection_id,
+                    &state.connections,
+                    &state.rate_limiter,
+                    &state.config,
+                )
+                .await
             }
+            _ => Ok(()),
+        };
+        if result.is_err() {
+            break;
         }
     }
-    service::on_disconnect::on_disconnect(connection_id, &database).await?;
+    state.notifier.remove_connection(connection_id);
+    service::on_disconnect::on_disconnect(connection_id, &state.connections).await?;
     Ok(())
 }
-
-async fn wait_for_message(
-    connection_id: &str,
-    notifier_local: &Arc<NotifierLocal>,
-) -> Option<Result<Message, LogicError>> {
-    let socket = notifier_local.get_connection(connection_id).unwrap();
-    let mut socket = socket.lock().await;
-    socket
-        .next()
-        .await
-        .map(|result| result.map_err(|e| LogicError::WebsocketError(e.to_string())))
-}
@@ -18,4 +18,26 @@ resource "aws_dynamodb_table" "websocket_connection" {
     hash_key        = "room_id"
     projection_type = "ALL"
   }
+
+  # Removes records whose $disconnect never arrived
+  ttl {
+    attribute_name = "ttl"
+    enabled        = true
+  }
+}
+
+resource "aws_dynamodb_table" "rate_limit" {
+  name         = "${local.prefix}RateLimit"
+  hash_key     = "id"
+  billing_mode = "PAY_PER_REQUEST"
+  attribute {
+    name = "id"
+    type = "S"
+  }
+
+  # Removes buckets once they have refilled
+  ttl {
+    attribute_name = "ttl"
+    enabled        = true
+  }
 }
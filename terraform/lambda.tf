@@ -18,12 +18,49 @@ resource "aws_lambda_function" "api" {
   ]
   environment {
     variables = {
-      WEBSOCKET_TABLE_NAME = aws_dynamodb_table.websocket_connection.name,
-      API_GATEWAY_URL      = aws_apigatewayv2_stage.websocket.invoke_url,
+      WEBSOCKET_TABLE_NAME  = aws_dynamodb_table.websocket_connection.name,
+      RATE_LIMIT_TABLE_NAME = aws_dynamodb_table.rate_limit.name,
+      API_GATEWAY_URL       = aws_apigatewayv2_stage.websocket.invoke_url,
     }
   }
 }
 
+resource "aws_cloudwatch_log_group" "authorizer" {
+  name              = "/aws/lambda/${local.prefix}-Authorizer"
+  retention_in_days = 90
+}
+
+resource "aws_lambda_function" "authorizer" {
+  package_type  = "Image"
+  image_uri     = "${aws_ecr_repository.lambda.repository_url}@${data.aws_ecr_image.lambda.id}"
+  function_name = "${local.prefix}-Authorizer"
+  role          = aws_iam_role.lambda_authorizer.arn
+  timeout       = 5
+  image_config {
+    entry_point = ["/ws_authorizer"]
+  }
+  depends_on = [
+    aws_cloudwatch_log_group.authorizer,
+    terraform_data.lambda_push,
+  ]
+  environment {
+    variables = {
+      AUTH_SECRET = var.auth_secret,
+    }
+  }
+}
+
+resource "aws_iam_role" "lambda_authorizer" {
+  name               = "${local.prefix}-Authorizer"
+  description        = "Allows Lambda run"
+  assume_role_policy = data.aws_iam_policy_document.lambda_assume_role.json
+}
+
+resource "aws_iam_role_policy_attachment" "execute_authorizer_lambda" {
+  role       = aws_iam_role.lambda_authorizer.name
+  policy_arn = "arn:aws:iam::aws:policy/service-role/AWSLambdaBasicExecutionRole"
+}
+
 resource "aws_iam_role" "lambda_api" {
   name               = "${local.prefix}-API"
   description        = "Allows Lambda run"
@@ -82,7 +119,8 @@ data "aws_iam_policy_document" "access_dynamodb" {
     effect = "Allow"
     resources = [
       aws_dynamodb_table.websocket_connection.arn,
-      "${aws_dynamodb_table.websocket_connection.arn}/index/*"
+      "${aws_dynamodb_table.websocket_connection.arn}/index/*",
+      aws_dynamodb_table.rate_limit.arn,
     ]
   }
 }
@@ -21,6 +21,12 @@ provider "aws" {
 
 data "aws_caller_identity" "identity" {}
 
+variable "auth_secret" {
+  description = "Secret used to sign and validate websocket connection tokens."
+  type        = string
+  sensitive   = true
+}
+
 locals {
   region           = "eu-west-2"
   prefix           = "RustChatRoom"